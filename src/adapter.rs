@@ -12,8 +12,13 @@ type HyperRequest = hyper::Request<BoxBody<Bytes, hyper::Error>>;
 
 pub struct RequestAdapter {
     pub inner: HyperRequest,
+    pub sniffed: Option<String>,
 }
 
+/// Response extension holding the path of a file whose type was sniffed
+#[derive(Clone)]
+pub struct Sniffed(pub String);
+
 impl Request<HyperResponse> for RequestAdapter {
     fn method(&self) -> &[u8] {
         self.inner.method().as_str().as_bytes()
@@ -45,9 +50,16 @@ impl Request<HyperResponse> for RequestAdapter {
                 map.append(name, value);
             }
         });
+        if let Some(path) = self.sniffed {
+            builder = builder.extension(Sniffed(path));
+        }
         let body = body.map(Self::full).unwrap_or_else(Self::empty);
         builder.body(body).unwrap()
     }
+
+    fn sniffed(&mut self, path: &str) {
+        self.sniffed = Some(path.to_string());
+    }
 }
 
 impl RequestAdapter {
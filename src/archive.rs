@@ -0,0 +1,142 @@
+use crate::path::{self, extension, filename};
+use crate::types::headers_for_path;
+use brotli::CompressorWriter;
+use hyper::Uri;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
+
+pub struct ZipSummary {
+    pub files: usize,
+    pub compressed: usize,
+    // relative path and reason
+    pub skipped: Vec<(String, &'static str)>,
+    pub warnings: Vec<String>,
+}
+
+/// Checks the content of a `.307` or `.308` file, returning the location it redirects to.
+pub fn redirect_location(content: &[u8]) -> Result<&str, String> {
+    let end = content
+        .iter()
+        .position(|&b| b.is_ascii_whitespace())
+        .unwrap_or(content.len());
+    let location = std::str::from_utf8(&content[..end])
+        .map_err(|_| "the location is not valid UTF-8".to_string())?;
+    if location.is_empty() {
+        return Err("the location is empty".to_string());
+    }
+    let uri = location
+        .parse::<Uri>()
+        .map_err(|err| format!("invalid location {location}: {err}"))?;
+    match uri.scheme_str() {
+        Some("http") | Some("https") if uri.authority().is_some() => Ok(location),
+        None if location.starts_with('/') && !location.starts_with("//") => Ok(location),
+        _ => Err(format!(
+            "the location {location} is neither an absolute path nor an http(s) url"
+        )),
+    }
+}
+
+/// Packs the root into a zip for zip_static_handler: entries are stored uncompressed,
+/// with a `.br` sibling for compressible types when brotli makes them smaller.
+/// Dotfiles, existing `.br` files and files without a known type are left out.
+/// Fails without writing anything when a `.307` or `.308` file is invalid.
+pub fn build_zip(
+    root: &Path,
+    output: &Path,
+    quality: u32,
+    dot_paths: &[&str],
+) -> Result<ZipSummary, String> {
+    let mut files = vec![];
+    path::files(root, &mut files).map_err(|err| format!("{}: {err}", root.display()))?;
+    let output_canonical = output.canonicalize().ok();
+    let mut entries = vec![];
+    let mut summary = ZipSummary {
+        files: 0,
+        compressed: 0,
+        skipped: vec![],
+        warnings: vec![],
+    };
+    let mut errors = vec![];
+    for file in files {
+        if output_canonical.is_some() && file.canonicalize().ok() == output_canonical {
+            continue;
+        }
+        let Some(relative) = path::relative(root, &file) else {
+            summary
+                .skipped
+                .push((file.display().to_string(), "not UTF-8"));
+            continue;
+        };
+        if path::hidden(&relative, dot_paths) {
+            summary.skipped.push((relative, "dotfile"));
+            continue;
+        }
+        if relative.ends_with(".br") {
+            summary.skipped.push((relative, "regenerated"));
+            continue;
+        }
+        let filename = filename(&relative);
+        let extension = extension(filename);
+        let Some(headers) = headers_for_path(&relative) else {
+            summary.skipped.push((relative, "unknown extension"));
+            continue;
+        };
+        let content = std::fs::read(&file).map_err(|err| format!("{relative}: {err}"))?;
+        if headers.redirection {
+            if let Err(err) = redirect_location(&content) {
+                errors.push(format!("{relative}: {err}"));
+                continue;
+            }
+            let stem = &relative[..relative.len() - extension.len() - 1];
+            if root.join(stem).is_file() || root.join(format!("{stem}.html")).is_file() {
+                summary.warnings.push(format!(
+                    "{relative} is never used, /{stem} is served by a file"
+                ));
+            }
+        }
+        let compressed = if headers.compressible {
+            Some(brotli(&content, quality)).filter(|it| it.len() < content.len())
+        } else {
+            None
+        };
+        entries.push((relative, content, compressed));
+    }
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let file = File::create(output).map_err(|err| format!("{}: {err}", output.display()))?;
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+    // stored, as the handler serves the entries as they are, and dated for reproducible builds
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .last_modified_time(DateTime::default());
+    let mut write = |name: &str, content: &[u8]| {
+        zip.start_file(name, options)
+            .map_err(|err| format!("{name}: {err}"))?;
+        zip.write_all(content)
+            .map_err(|err| format!("{name}: {err}"))
+    };
+    for (relative, content, compressed) in entries {
+        write(&relative, &content)?;
+        summary.files += 1;
+        if let Some(compressed) = compressed {
+            write(&format!("{relative}.br"), &compressed)?;
+            summary.compressed += 1;
+        }
+    }
+    zip.finish()
+        .and_then(|mut it| it.flush().map_err(Into::into))
+        .map_err(|err| format!("{}: {err}", output.display()))?;
+    Ok(summary)
+}
+
+fn brotli(content: &[u8], quality: u32) -> Vec<u8> {
+    let mut writer = CompressorWriter::new(Vec::new(), 4096, quality, 22);
+    // writing to a Vec can't fail
+    let _ = writer.write_all(content);
+    writer.into_inner()
+}
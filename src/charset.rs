@@ -0,0 +1,81 @@
+use crate::types::{DEFAULT_CHARSET, is_text};
+use std::str::FromStr;
+
+/// Charset parameters of text content types, replacing the default `utf-8`.
+pub struct Charsets {
+    /// Charset of the text types without a rule (None to send no parameter)
+    pub default: Option<String>,
+    pub rules: Vec<CharsetRule>,
+    /// Look at the content: a BOM sets the charset and content that is not valid UTF-8
+    /// drops the `utf-8` parameter, so that browsers guess it instead.
+    pub detect: bool,
+}
+
+/// The charset of a content type (`text/csv=windows-1252`, or `text/markdown=none`).
+#[derive(Clone, Debug)]
+pub struct CharsetRule {
+    pub content_type: String,
+    pub charset: Option<String>,
+}
+
+impl FromStr for CharsetRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (content_type, charset) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <content-type>=<charset|none>, got {s}"))?;
+        let content_type = content_type.trim().to_ascii_lowercase();
+        if !content_type.contains('/') {
+            return Err(format!("invalid content type {content_type}"));
+        }
+        let charset = match charset.trim() {
+            "" => return Err(format!("missing charset for {content_type}")),
+            it if it.eq_ignore_ascii_case("none") => None,
+            it => Some(it.to_ascii_lowercase()),
+        };
+        Ok(Self {
+            content_type,
+            charset,
+        })
+    }
+}
+
+impl Charsets {
+    /// The content type to send instead of the given one, if any. The content is the
+    /// decoded one, when it is known.
+    pub(crate) fn content_type(&self, value: &[u8], content: Option<&[u8]>) -> Option<Vec<u8>> {
+        let essence = value
+            .split(|&it| it == b';')
+            .next()
+            .unwrap_or(value)
+            .trim_ascii();
+        let charset = match self
+            .rules
+            .iter()
+            .find(|it| it.content_type.as_bytes().eq_ignore_ascii_case(essence))
+        {
+            Some(rule) => rule.charset.as_deref(),
+            None if is_text(essence) => self.default.as_deref(),
+            None => return None,
+        };
+        let charset = match content.filter(|_| self.detect) {
+            Some([0xef, 0xbb, 0xbf, ..]) => Some("utf-8"),
+            Some([0xfe, 0xff, ..]) => Some("utf-16be"),
+            Some([0xff, 0xfe, ..]) => Some("utf-16le"),
+            Some(content)
+                if charset.is_some_and(|it| it.eq_ignore_ascii_case(DEFAULT_CHARSET))
+                    && std::str::from_utf8(content).is_err() =>
+            {
+                None
+            }
+            _ => charset,
+        };
+        let mut content_type = essence.to_vec();
+        if let Some(charset) = charset {
+            content_type.extend_from_slice(b"; charset=");
+            content_type.extend_from_slice(charset.as_bytes());
+        }
+        Some(content_type)
+    }
+}
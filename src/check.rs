@@ -0,0 +1,205 @@
+use crate::handler::Handler;
+use crate::http::headers::Line;
+use crate::http::request::Request;
+use crate::http::response::StatusCode;
+use crate::path;
+use crate::types::{DefaultHeaderSelector, headers_for_path};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum CheckFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+/// The response served for a path, without the body
+#[derive(Serialize)]
+pub struct Checked {
+    pub path: String,
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub length: usize,
+}
+
+impl Checked {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|it| it.as_str())
+    }
+}
+
+pub(crate) struct CheckRequest {
+    pub(crate) path: String,
+}
+
+impl Request<Checked> for CheckRequest {
+    fn method(&self) -> &[u8] {
+        b"GET"
+    }
+
+    fn path(&self) -> &[u8] {
+        self.path.as_bytes()
+    }
+
+    fn first_header_value(&self, _key: &'static [u8]) -> Option<&[u8]> {
+        None
+    }
+
+    fn response<'b>(
+        self,
+        code: StatusCode,
+        headers: impl Iterator<Item = &'b Line>,
+        body: Option<&'b [u8]>,
+    ) -> Checked {
+        let mut map = BTreeMap::<String, String>::new();
+        for line in headers {
+            let name = String::from_utf8_lossy(line.key).to_ascii_lowercase();
+            let value = String::from_utf8_lossy(line.value.as_ref()).into_owned();
+            map.entry(name)
+                .and_modify(|it| {
+                    it.push_str(", ");
+                    it.push_str(&value);
+                })
+                .or_insert(value);
+        }
+        Checked {
+            path: self.path,
+            status: code.into(),
+            headers: map,
+            length: body.map(|it| it.len()).unwrap_or(0),
+        }
+    }
+}
+
+/// Every path a request could use to reach a file under the root, sorted.
+/// Dotfiles (except the allowed dot paths) are left out, as they are never served.
+pub fn site_paths(root: &Path, prefix: &str, dot_paths: &[&str]) -> std::io::Result<Vec<String>> {
+    let mut files = vec![];
+    path::files(root, &mut files)?;
+    let prefix = if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("/{prefix}")
+    };
+    let mut paths = vec![];
+    for file in files {
+        let Some(relative) = path::relative(root, &file) else {
+            continue;
+        };
+        // served in place of the uncompressed file
+        if relative.ends_with(".br") || path::hidden(&relative, dot_paths) {
+            continue;
+        }
+        if relative == "index.html" || relative == "index.307" || relative == "index.308" {
+            paths.push(format!("{prefix}/"));
+        }
+        if let Some(stem) = relative
+            .strip_suffix(".html")
+            .or_else(|| relative.strip_suffix(".307"))
+            .or_else(|| relative.strip_suffix(".308"))
+        {
+            paths.push(format!("{prefix}/{stem}"));
+        }
+        if !relative.ends_with(".307") && !relative.ends_with(".308") {
+            paths.push(format!("{prefix}/{relative}"));
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+/// Files that would be served if their extension had a known type, sorted.
+/// Dotfiles (except the allowed dot paths) and `.br` files are left out, as they are not meant to be served directly.
+pub fn unmapped_files(root: &Path, dot_paths: &[&str]) -> std::io::Result<Vec<String>> {
+    let mut files = vec![];
+    path::files(root, &mut files)?;
+    let mut unmapped: Vec<String> = files
+        .iter()
+        .filter_map(|it| path::relative(root, it))
+        .filter(|it| !path::hidden(it, dot_paths) && !it.ends_with(".br"))
+        .filter(|it| headers_for_path(it).is_none())
+        .collect();
+    unmapped.sort();
+    Ok(unmapped)
+}
+
+pub(crate) fn handler(
+    root: &Path,
+    prefix: &str,
+    dot_paths: &[&str],
+) -> Handler<DefaultHeaderSelector> {
+    Handler {
+        root: Box::leak(root.to_path_buf().into_boxed_path()),
+        prefix: prefix.to_string().leak(),
+        header_selector: DefaultHeaderSelector,
+        cors: None,
+        sniff: false,
+        dot_paths: dot_paths
+            .iter()
+            .map(|it| &*it.to_string().leak())
+            .collect::<Vec<_>>()
+            .leak(),
+        charsets: None,
+        security: None,
+    }
+}
+
+/// Resolves every path of the site with the same rules as the server.
+pub async fn check(root: &Path, prefix: &str, dot_paths: &[&str]) -> std::io::Result<Vec<Checked>> {
+    let prefix = prefix.trim_matches('/');
+    let handler = handler(root, prefix, dot_paths);
+    let mut checked = vec![];
+    for path in site_paths(root, prefix, dot_paths)? {
+        checked.push(handler.handle(CheckRequest { path }).await);
+    }
+    Ok(checked)
+}
+
+pub fn print(checked: &[Checked], format: CheckFormat) {
+    match format {
+        CheckFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(checked).unwrap_or_default()
+        ),
+        CheckFormat::Table => {
+            let columns = [
+                "content-type",
+                "content-encoding",
+                "cache-control",
+                "location",
+            ];
+            let rows: Vec<Vec<&str>> = checked
+                .iter()
+                .map(|it| {
+                    let mut row = vec![it.path.as_str()];
+                    row.extend(columns.iter().map(|name| it.header(name).unwrap_or("-")));
+                    row
+                })
+                .collect();
+            let mut widths = vec!["path".len()];
+            widths.extend(columns.iter().map(|it| it.len()));
+            for row in rows.iter() {
+                for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                    *width = (*width).max(cell.len());
+                }
+            }
+            let line = |status: &str, cells: &[&str]| {
+                let mut line = format!("{status:<6} ");
+                for (cell, width) in cells.iter().zip(widths.iter()) {
+                    line.push_str(&format!("{cell:<width$}  "));
+                }
+                line.trim_end().to_string()
+            };
+            let mut header = vec!["path"];
+            header.extend(columns);
+            println!("{}", line("status", &header));
+            for (it, row) in checked.iter().zip(rows.iter()) {
+                println!("{}", line(&it.status.to_string(), row));
+            }
+        }
+    }
+}
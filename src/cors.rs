@@ -0,0 +1,100 @@
+use crate::http::headers::{
+    ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ALLOW, Line, VARY,
+};
+
+pub(crate) const DEFAULT_METHODS: &[u8] = b"GET, HEAD, OPTIONS";
+
+pub struct Cors {
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    pub headers: Vec<String>,
+    pub credentials: bool,
+    pub max_age: Option<u32>,
+}
+
+impl Cors {
+    fn allows_any_origin(&self) -> bool {
+        self.origins.iter().any(|it| it == "*")
+    }
+
+    fn allows_origin(&self, origin: &[u8]) -> bool {
+        self.allows_any_origin()
+            || self
+                .origins
+                .iter()
+                .any(|it| it.as_bytes().eq_ignore_ascii_case(origin))
+    }
+
+    pub fn response_headers(&self, origin: Option<&[u8]>) -> Vec<Line> {
+        let mut headers = vec![];
+        if self.allows_any_origin() && !self.credentials {
+            if origin.is_some() {
+                headers.push(Line::with_array_ref_value(
+                    ACCESS_CONTROL_ALLOW_ORIGIN,
+                    b"*",
+                ));
+            }
+            return headers;
+        }
+        // the allowed origin is echoed back, so caches need to key on it
+        headers.push(Line::with_array_ref_value(VARY, b"Origin"));
+        if let Some(origin) = origin
+            && self.allows_origin(origin)
+        {
+            headers.push(Line::with_owned_value(
+                ACCESS_CONTROL_ALLOW_ORIGIN,
+                origin.to_vec(),
+            ));
+            if self.credentials {
+                headers.push(Line::with_array_ref_value(
+                    ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                    b"true",
+                ));
+            }
+        }
+        headers
+    }
+
+    pub fn preflight_headers(
+        &self,
+        origin: Option<&[u8]>,
+        request_headers: Option<&[u8]>,
+    ) -> Vec<Line> {
+        let mut headers = self.response_headers(origin);
+        headers.push(Line::with_slice_value(ALLOW, DEFAULT_METHODS));
+        if !origin.is_some_and(|it| self.allows_origin(it)) {
+            return headers;
+        }
+        headers.push(if self.methods.is_empty() {
+            Line::with_slice_value(ACCESS_CONTROL_ALLOW_METHODS, DEFAULT_METHODS)
+        } else {
+            Line::with_owned_value(
+                ACCESS_CONTROL_ALLOW_METHODS,
+                self.methods.join(", ").into_bytes(),
+            )
+        });
+        if !self.headers.is_empty() {
+            headers.push(Line::with_owned_value(
+                ACCESS_CONTROL_ALLOW_HEADERS,
+                self.headers.join(", ").into_bytes(),
+            ));
+        } else if let Some(request_headers) = request_headers {
+            headers.push(Line::with_array_ref_value(
+                VARY,
+                b"Access-Control-Request-Headers",
+            ));
+            headers.push(Line::with_owned_value(
+                ACCESS_CONTROL_ALLOW_HEADERS,
+                request_headers.to_vec(),
+            ));
+        }
+        if let Some(max_age) = self.max_age {
+            headers.push(Line::with_owned_value(
+                ACCESS_CONTROL_MAX_AGE,
+                format!("{max_age}").into_bytes(),
+            ));
+        }
+        headers
+    }
+}
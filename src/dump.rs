@@ -0,0 +1,47 @@
+use crate::forward::StatusRule;
+use colored::Colorize;
+use hyper::{HeaderMap, StatusCode};
+
+pub struct Dump {
+    pub paths: Vec<String>,
+    pub statuses: Vec<StatusRule>,
+}
+
+impl Dump {
+    pub fn matches(&self, path: &str, status: StatusCode) -> bool {
+        let path = path.split_once('?').map(|it| it.0).unwrap_or(path);
+        (self.paths.is_empty()
+            || self
+                .paths
+                .iter()
+                .any(|it| glob(it.as_bytes(), path.as_bytes())))
+            && (self.statuses.is_empty() || StatusRule::matches_any(&self.statuses, status))
+    }
+
+    pub fn print(&self, request_headers: &HeaderMap, response_headers: &HeaderMap) {
+        let mut lines = String::new();
+        for (prefix, headers) in [("> ", request_headers), ("< ", response_headers)] {
+            for (name, value) in headers {
+                lines.push_str(&format!(
+                    "     {prefix}{name}: {}\n",
+                    String::from_utf8_lossy(value.as_bytes())
+                ));
+            }
+        }
+        print!("{}", lines.dimmed());
+    }
+}
+
+// `*` matches within a path segment, `**` across segments and `?` a single character
+fn glob(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| glob(rest, &path[i..])),
+        [b'*', rest @ ..] => {
+            let segment = path.iter().position(|&it| it == b'/').unwrap_or(path.len());
+            (0..=segment).any(|i| glob(rest, &path[i..]))
+        }
+        [b'?', rest @ ..] => !path.is_empty() && path[0] != b'/' && glob(rest, &path[1..]),
+        [c, rest @ ..] => path.first() == Some(c) && glob(rest, &path[1..]),
+    }
+}
@@ -0,0 +1,610 @@
+use crate::http::headers::{ALLOW, Line};
+use crate::security::Security;
+use crate::types::default_headers;
+use clap::ValueEnum;
+use colored::Colorize;
+use http_body_util::BodyExt;
+use http_body_util::combinators::BoxBody;
+use hyper::body::Bytes;
+use hyper::header::{
+    CONNECTION, CONTENT_LOCATION, FORWARDED, HOST, HeaderMap, HeaderName, HeaderValue, LOCATION,
+    SET_COOKIE, UPGRADE,
+};
+use hyper::http::uri::Authority;
+use hyper::upgrade::OnUpgrade;
+use hyper::{Request, Response, StatusCode, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::io::copy_bidirectional;
+use tokio::spawn;
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::client::WebPkiServerVerifier;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{
+    ClientConfig, DigitallySignedStruct, Error, RootCertStore, SignatureScheme,
+};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+pub type ForwardClient = Client<HttpsConnector<HttpConnector>, BoxBody<Bytes, hyper::Error>>;
+
+#[derive(Debug)]
+struct UpstreamVerifier {
+    insecure: bool,
+    // only skips verification for localhost, 127.0.0.1 and ::1
+    insecure_loopback: bool,
+    webpki: Arc<WebPkiServerVerifier>,
+}
+
+impl ServerCertVerifier for UpstreamVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let name = server_name.to_str();
+        let loopback = name == "127.0.0.1" || name == "::1" || name == "localhost";
+        if self.insecure || (self.insecure_loopback && loopback) {
+            return Ok(ServerCertVerified::assertion());
+        }
+        let verified = self.webpki.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        );
+        // local dev servers usually have a self-signed certificate
+        if let Err(err) = verified.as_ref()
+            && loopback
+        {
+            println!(
+                "{} {name}: {err}, use --upstream-insecure-localhost to trust local upstreams",
+                "untrusted certificate for upstream".red()
+            );
+        }
+        verified
+    }
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.webpki.verify_tls12_signature(message, cert, dss)
+    }
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.webpki.verify_tls13_signature(message, cert, dss)
+    }
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.webpki.supported_verify_schemes()
+    }
+}
+
+pub struct UpstreamTls {
+    verifier: Arc<UpstreamVerifier>,
+}
+
+impl UpstreamTls {
+    pub fn new(
+        insecure: bool,
+        insecure_loopback: bool,
+        ca_files: &[PathBuf],
+    ) -> Result<Self, String> {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        for path in ca_files {
+            let certs = CertificateDer::pem_file_iter(path)
+                .map_err(|err| format!("failed to read {}: {err}", path.display()))?;
+            for cert in certs {
+                let cert =
+                    cert.map_err(|err| format!("failed to read {}: {err}", path.display()))?;
+                roots
+                    .add(cert)
+                    .map_err(|err| format!("invalid certificate in {}: {err}", path.display()))?;
+            }
+        }
+        let webpki = WebPkiServerVerifier::builder(Arc::new(roots))
+            .build()
+            .map_err(|err| format!("failed to create certificate verifier: {err}"))?;
+        Ok(Self {
+            verifier: Arc::new(UpstreamVerifier {
+                insecure,
+                insecure_loopback,
+                webpki,
+            }),
+        })
+    }
+}
+
+pub fn client(tls: &UpstreamTls, http1_only: bool) -> ForwardClient {
+    let config = ClientConfig::builder_with_protocol_versions(&[
+        &rustls::version::TLS13,
+        &rustls::version::TLS12,
+    ])
+    .dangerous()
+    .with_custom_certificate_verifier(tls.verifier.clone())
+    .with_no_client_auth();
+    let builder = HttpsConnectorBuilder::new()
+        .with_tls_config(config)
+        .https_or_http();
+    Client::builder(TokioExecutor::new()).build(if http1_only {
+        builder.enable_http1().build()
+    } else {
+        builder.enable_all_versions().build()
+    })
+}
+
+pub(crate) fn is_upgrade<B>(request: &Request<B>) -> bool {
+    request.headers().contains_key(UPGRADE)
+        && request
+            .headers()
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|it| it.to_str().ok())
+            .flat_map(|it| it.split(','))
+            .any(|it| it.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+fn tunnel(downstream: OnUpgrade, upstream: OnUpgrade) {
+    spawn(async move {
+        match tokio::try_join!(downstream, upstream) {
+            Ok((downstream, upstream)) => {
+                let _ =
+                    copy_bidirectional(&mut TokioIo::new(downstream), &mut TokioIo::new(upstream))
+                        .await;
+            }
+            Err(err) => {
+                println!("{}\n{err:?}", "error on upgraded connection".red());
+            }
+        }
+    });
+}
+
+pub fn forward_uri(origin: &Uri, uri: &Uri) -> Result<Uri, hyper::http::Error> {
+    let forward_uri = Uri::builder();
+    let forward_uri = if let Some(scheme) = origin.scheme().or_else(|| uri.scheme()) {
+        forward_uri.scheme(scheme.clone())
+    } else {
+        forward_uri
+    };
+    let forward_uri = if let Some(authority) = origin.authority().or_else(|| uri.authority()) {
+        forward_uri.authority(authority.clone())
+    } else {
+        forward_uri
+    };
+    let forward_uri = if let Some(path_and_query) = uri.path_and_query() {
+        forward_uri.path_and_query(path_and_query.clone())
+    } else {
+        forward_uri
+    };
+    forward_uri.build()
+}
+
+#[derive(Debug)]
+pub enum ForwardError {
+    // the upstream origin and the request path don't make a valid uri
+    Uri(hyper::http::Error),
+    Client(hyper_util::client::legacy::Error),
+}
+
+impl From<hyper_util::client::legacy::Error> for ForwardError {
+    fn from(err: hyper_util::client::legacy::Error) -> Self {
+        Self::Client(err)
+    }
+}
+
+pub struct ForwardOptions {
+    pub tls: UpstreamTls,
+    // sends the upstream authority as the host instead of the dev server one
+    pub rewrite_host: bool,
+    // adds the static content security headers missing from upstream responses
+    pub security_headers: bool,
+    // the policies replacing the default ones in those headers
+    pub security: Option<Security>,
+}
+
+fn add_forwarding_headers<B>(request: &mut Request<B>, client_address: SocketAddr) {
+    let ip = client_address.ip();
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|it| it.to_str().ok())
+        .or_else(|| request.uri().authority().map(|it| it.as_str()))
+        .map(|it| it.to_string());
+    let node = match ip {
+        IpAddr::V4(ip) => format!("{ip}"),
+        IpAddr::V6(ip) => format!("\"[{ip}]\""),
+    };
+    let forwarded = match host.as_ref() {
+        Some(host) => format!("for={node};proto=https;host=\"{host}\""),
+        None => format!("for={node};proto=https"),
+    };
+    let headers = request.headers_mut();
+    append_to_list(headers, FORWARDED, forwarded);
+    append_to_list(headers, X_FORWARDED_FOR, ip.to_string());
+    headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static("https"));
+    if let Some(host) = host
+        && let Ok(host) = HeaderValue::from_str(&host)
+    {
+        headers.insert(X_FORWARDED_HOST, host);
+    }
+}
+
+fn append_to_list(headers: &mut HeaderMap, name: HeaderName, value: String) {
+    let value = match headers.get(&name).and_then(|it| it.to_str().ok()) {
+        Some(previous) => format!("{previous}, {value}"),
+        None => value,
+    };
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(name, value);
+    }
+}
+
+pub struct Upstream {
+    pub origin: Uri,
+    options: Arc<ForwardOptions>,
+    client: ForwardClient,
+    // upgrades (websockets) need an http/1.1 connection
+    upgrade_client: ForwardClient,
+}
+
+impl Upstream {
+    pub fn new(origin: Uri, options: Arc<ForwardOptions>) -> Self {
+        Self {
+            client: client(&options.tls, false),
+            upgrade_client: client(&options.tls, true),
+            origin,
+            options,
+        }
+    }
+
+    pub async fn forward(
+        &self,
+        mut request: Request<BoxBody<Bytes, hyper::Error>>,
+        client_address: SocketAddr,
+        route: Option<&Route>,
+    ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, ForwardError> {
+        let public_host = request
+            .headers()
+            .get(HOST)
+            .and_then(|it| it.to_str().ok())
+            .map(|it| it.to_string());
+        add_forwarding_headers(&mut request, client_address);
+        if self.options.rewrite_host
+            && let Some(authority) = self.origin.authority()
+            && let Ok(host) = HeaderValue::from_str(authority.as_str())
+        {
+            request.headers_mut().insert(HOST, host);
+        }
+        *request.uri_mut() = forward_uri(&self.origin, request.uri()).map_err(ForwardError::Uri)?;
+        let mut response = if !is_upgrade(&request) {
+            self.client.request(request).await?
+        } else {
+            let downstream = hyper::upgrade::on(&mut request);
+            let mut response = self.upgrade_client.request(request).await?;
+            if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+                tunnel(downstream, hyper::upgrade::on(&mut response));
+            }
+            response
+        };
+        self.rewrite_response_headers(response.headers_mut(), public_host.as_deref(), route);
+        let (parts, body) = response.into_parts();
+        Ok(Response::from_parts(parts, body.boxed()))
+    }
+
+    fn rewrite_response_headers(
+        &self,
+        headers: &mut HeaderMap,
+        public_host: Option<&str>,
+        route: Option<&Route>,
+    ) {
+        let upstream_origin = match (self.origin.scheme_str(), self.origin.authority()) {
+            (Some(scheme), Some(authority)) => Some(format!("{scheme}://{authority}")),
+            _ => None,
+        };
+        let public_origin = public_host.map(|it| format!("https://{it}"));
+        for name in [LOCATION, CONTENT_LOCATION] {
+            if let Some(value) = headers.get(&name).and_then(|it| it.to_str().ok())
+                && let Some(value) = rewrite_location(
+                    value,
+                    upstream_origin.as_deref(),
+                    public_origin.as_deref(),
+                    route,
+                )
+                && let Ok(value) = HeaderValue::from_str(&value)
+            {
+                headers.insert(name, value);
+            }
+        }
+        let public_hostname = public_host.map(|it| {
+            Authority::from_str(it)
+                .map(|it| it.host().to_string())
+                .unwrap_or_else(|_| it.to_string())
+        });
+        let cookies = headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|it| it.to_str().ok())
+            .map(|it| rewrite_cookie(it, public_hostname.as_deref(), route))
+            .collect::<Vec<_>>();
+        if !cookies.is_empty() {
+            headers.remove(SET_COOKIE);
+            for cookie in cookies {
+                if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+                    headers.append(SET_COOKIE, cookie);
+                }
+            }
+        }
+        if self.options.security_headers {
+            let mut lines: Vec<Line> = default_headers().cloned().collect();
+            if let Some(security) = self.options.security.as_ref() {
+                security.apply(&mut lines);
+            }
+            for line in lines {
+                if line.key == ALLOW {
+                    continue;
+                }
+                if let Ok(name) = HeaderName::from_bytes(line.key)
+                    && !headers.contains_key(&name)
+                    && let Ok(value) = HeaderValue::from_bytes(line.value.as_ref())
+                {
+                    headers.insert(name, value);
+                }
+            }
+        }
+    }
+}
+
+/// The Location of an upstream response as seen through the dev server, None when it points
+/// elsewhere.
+pub fn rewrite_location(
+    value: &str,
+    upstream_origin: Option<&str>,
+    public_origin: Option<&str>,
+    route: Option<&Route>,
+) -> Option<String> {
+    let (origin, path) = if value.starts_with('/') && !value.starts_with("//") {
+        ("", value)
+    } else if let (Some(upstream_origin), Some(public_origin)) = (upstream_origin, public_origin)
+        && value.len() >= upstream_origin.len()
+        && value.is_char_boundary(upstream_origin.len())
+        && value[..upstream_origin.len()].eq_ignore_ascii_case(upstream_origin)
+        && value[upstream_origin.len()..]
+            .chars()
+            .next()
+            .is_none_or(|it| it == '/' || it == '?' || it == '#')
+    {
+        (public_origin, &value[upstream_origin.len()..])
+    } else {
+        return None;
+    };
+    let split = path.find(['?', '#']).unwrap_or(path.len());
+    let (path, rest) = path.split_at(split);
+    let path = match route {
+        Some(route) => route.restore_path(path),
+        None => path.to_string(),
+    };
+    Some(format!("{origin}{path}{rest}"))
+}
+
+/// The Set-Cookie of an upstream response without a Domain the browser would reject, and with
+/// its Path mapped back to the dev server one.
+pub fn rewrite_cookie(
+    cookie: &str,
+    public_hostname: Option<&str>,
+    route: Option<&Route>,
+) -> String {
+    let mut attributes = cookie.split(';');
+    let mut rewritten = attributes.next().unwrap_or_default().to_string();
+    for attribute in attributes {
+        let (name, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        if name.trim().eq_ignore_ascii_case("domain") {
+            // the browser rejects cookies for a domain other than the dev server one
+            if public_hostname.is_none_or(|it| !it.eq_ignore_ascii_case(value.trim())) {
+                continue;
+            }
+        } else if name.trim().eq_ignore_ascii_case("path")
+            && let Some(route) = route
+        {
+            rewritten.push_str(&format!("; Path={}", route.restore_path(value.trim())));
+            continue;
+        }
+        rewritten.push(';');
+        rewritten.push_str(attribute);
+    }
+    rewritten
+}
+
+#[derive(Clone, Debug)]
+pub struct Route {
+    pub prefix: String,
+    pub origin: Uri,
+    // replaces the matched prefix when the origin has a path (empty to strip it)
+    pub rewrite: Option<String>,
+}
+
+impl Route {
+    fn matches(&self, path: &str) -> bool {
+        self.prefix == "/"
+            || path
+                .strip_prefix(self.prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    // maps an upstream path back to the dev server path, reversing rewrite_uri
+    pub fn restore_path(&self, path: &str) -> String {
+        let Some(rewrite) = self.rewrite.as_ref() else {
+            return path.to_string();
+        };
+        let rest = match path.strip_prefix(rewrite.as_str()) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+            _ => return path.to_string(),
+        };
+        let prefix = if self.prefix == "/" {
+            ""
+        } else {
+            self.prefix.as_str()
+        };
+        match rest {
+            "" | "/" if !prefix.is_empty() => prefix.to_string(),
+            "" => "/".to_string(),
+            _ => format!("{prefix}{rest}"),
+        }
+    }
+
+    pub fn rewrite_uri(&self, uri: &Uri) -> Uri {
+        let Some(rewrite) = self.rewrite.as_ref() else {
+            return uri.clone();
+        };
+        let path_and_query = uri.path_and_query().map(|it| it.as_str()).unwrap_or("/");
+        let rest = if self.prefix == "/" {
+            path_and_query
+        } else {
+            path_and_query
+                .strip_prefix(self.prefix.as_str())
+                .unwrap_or(path_and_query)
+        };
+        let path_and_query = format!("{rewrite}{rest}");
+        let path_and_query = if path_and_query.starts_with('/') {
+            path_and_query
+        } else {
+            format!("/{path_and_query}")
+        };
+        Uri::from_str(&path_and_query).unwrap_or_else(|_| uri.clone())
+    }
+}
+
+impl FromStr for Route {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (prefix, origin) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <prefix>=<origin>, got {s}"))?;
+        let prefix = prefix.trim_end_matches('/');
+        let prefix = if prefix.starts_with('/') {
+            prefix.to_string()
+        } else {
+            format!("/{prefix}")
+        };
+        // Uri normalizes a missing path to "/", so look at the raw value instead
+        let rewrite = origin
+            .split_once("://")
+            .and_then(|(_, rest)| rest.find('/').map(|it| &rest[it..]))
+            .map(|it| it.trim_end_matches('/').to_string());
+        let origin =
+            Uri::from_str(origin).map_err(|err| format!("invalid origin {origin}: {err}"))?;
+        if !matches!(origin.scheme_str(), Some("http" | "https")) {
+            return Err(format!(
+                "origin {origin} needs an http:// or https:// scheme"
+            ));
+        }
+        if origin.authority().is_none() {
+            return Err(format!("origin {origin} has no host"));
+        }
+        Ok(Self {
+            prefix,
+            origin,
+            rewrite,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum ForwardPolicy {
+    // serve static content, forward when the handler response matches --forward-on
+    #[default]
+    StaticFirst,
+    // forward first, serve static content when the upstream response matches --fallback-on
+    ProxyFirst,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum StatusRule {
+    Exact(u16),
+    // 4xx, 5xx, ...
+    Class(u16),
+}
+
+impl StatusRule {
+    pub fn matches(&self, status: StatusCode) -> bool {
+        match self {
+            StatusRule::Exact(code) => status.as_u16() == *code,
+            StatusRule::Class(class) => status.as_u16() / 100 == *class,
+        }
+    }
+
+    pub fn matches_any(rules: &[StatusRule], status: StatusCode) -> bool {
+        rules.iter().any(|it| it.matches(status))
+    }
+}
+
+impl FromStr for StatusRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected a status code (404) or class (4xx), got {s}");
+        if let Some(class) = s.strip_suffix("xx").or_else(|| s.strip_suffix("XX")) {
+            match class.parse::<u16>() {
+                Ok(class) if (1..=5).contains(&class) => Ok(StatusRule::Class(class)),
+                _ => Err(invalid()),
+            }
+        } else {
+            match s.parse::<u16>() {
+                Ok(code) if (100..600).contains(&code) => Ok(StatusRule::Exact(code)),
+                _ => Err(invalid()),
+            }
+        }
+    }
+}
+
+pub struct Proxy {
+    pub route: Route,
+    pub upstream: Upstream,
+}
+
+impl Proxy {
+    pub fn new(route: Route, options: Arc<ForwardOptions>) -> Self {
+        let upstream = Upstream::new(route.origin.clone(), options);
+        Self { route, upstream }
+    }
+
+    pub async fn forward(
+        &self,
+        mut request: Request<BoxBody<Bytes, hyper::Error>>,
+        client_address: SocketAddr,
+    ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, ForwardError> {
+        *request.uri_mut() = self.route.rewrite_uri(request.uri());
+        self.upstream
+            .forward(request, client_address, Some(&self.route))
+            .await
+    }
+}
+
+pub fn proxy_for<'a>(proxies: &'a [Proxy], path: &str) -> Option<&'a Proxy> {
+    proxies
+        .iter()
+        .filter(|it| it.route.matches(path))
+        .max_by_key(|it| it.route.prefix.len())
+}
@@ -1,39 +1,103 @@
+use crate::charset::Charsets;
+use crate::cors::{Cors, DEFAULT_METHODS};
 use crate::http::headers::{
-    CONTENT_ENCODING, CONTENT_LENGTH, ETAG, IF_MATCH, IF_NONE_MATCH, LOCATION, Line,
+    ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ALLOW, CONTENT_ENCODING,
+    CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, LOCATION, Line, ORIGIN,
+    TRANSFER_ENCODING,
 };
 use crate::http::method;
 use crate::http::request::Request;
 use crate::http::response::StatusCode;
-use crate::path::{extension, filename};
+use crate::path::{escapes_root, extension, filename, hidden};
+use crate::security::Security;
 use crc32fast::hash;
+use std::io::Read;
+use std::path::Path;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 
+const SNIFF_LENGTH: u64 = 512;
+
+/// Dot directories served despite the dotfile rule when none are configured.
+pub const DEFAULT_DOT_PATHS: &[&str] = &[".well-known"];
+
+/// The configured dot paths without their surrounding slashes, or the default ones.
+pub fn dot_paths(paths: &[String]) -> Vec<&str> {
+    if paths.is_empty() {
+        DEFAULT_DOT_PATHS.to_vec()
+    } else {
+        paths.iter().map(|it| it.trim_matches('/')).collect()
+    }
+}
+
 pub struct Handler<T: HeaderSelector> {
+    pub root: &'static Path,
     pub prefix: &'static str,
     pub header_selector: T,
+    pub cors: Option<&'static Cors>,
+    /// Whether files without a known extension are typed from their first bytes
+    pub sniff: bool,
+    /// Dotfiles and dot directories that are served, as paths relative to the root
+    pub dot_paths: &'static [&'static str],
+    /// Charset parameters replacing the default `utf-8` of text types
+    pub charsets: Option<&'static Charsets>,
+    /// Embedder and content security policies replacing the default ones
+    pub security: Option<&'static Security>,
 }
 
 impl<T: HeaderSelector> Handler<T> {
-    pub async fn handle<Resp, Req: Request<Resp>>(&self, request: Req) -> Resp {
+    pub async fn handle<Resp, Req: Request<Resp>>(&self, mut request: Req) -> Resp {
         let method = request.method();
         let path = String::from_utf8_lossy(request.path());
-        if let Some(value) = request.first_header_value(CONTENT_LENGTH)
-            && value != b"0"
+        let origin = request.first_header_value(ORIGIN);
+        let cors_headers = self
+            .cors
+            .map(|cors| cors.response_headers(origin))
+            .unwrap_or_default();
+        if request
+            .first_header_value(CONTENT_LENGTH)
+            .is_some_and(|it| it != b"0")
+            || request.first_header_value(TRANSFER_ENCODING).is_some()
         {
             return request.response(
                 StatusCode::BadRequest,
-                self.header_selector.error_headers().iter(),
+                self.header_selector
+                    .error_headers()
+                    .iter()
+                    .chain(cors_headers.iter()),
                 None::<&[u8]>,
             );
         }
+        if method == method::OPTIONS
+            && let Some(cors) = self.cors
+        {
+            let headers = if request
+                .first_header_value(ACCESS_CONTROL_REQUEST_METHOD)
+                .is_some()
+            {
+                cors.preflight_headers(
+                    origin,
+                    request.first_header_value(ACCESS_CONTROL_REQUEST_HEADERS),
+                )
+            } else {
+                cors.preflight_headers(None, None)
+            };
+            return request.response(StatusCode::NoContent, headers.iter(), None);
+        }
         let is_get = match method {
             method::GET => true,
             method::HEAD => false,
             _ => {
+                let mut headers = self.header_selector.error_headers().to_vec();
+                // preflight requests are answered too
+                if self.cors.is_some() {
+                    for line in headers.iter_mut().filter(|it| it.key == ALLOW) {
+                        *line = Line::with_slice_value(ALLOW, DEFAULT_METHODS);
+                    }
+                }
                 return request.response(
                     StatusCode::MethodNotAllowed,
-                    self.header_selector.error_headers().iter(),
+                    headers.iter().chain(cors_headers.iter()),
                     None::<&[u8]>,
                 );
             }
@@ -46,21 +110,38 @@ impl<T: HeaderSelector> Handler<T> {
                     .header_selector
                     .headers_for_extension(path_without_trailing_slash, "308")
                 {
-                    let location = format!("/{}{path_without_trailing_slash}", self.prefix);
+                    let location = if self.prefix.is_empty() {
+                        format!("/{path_without_trailing_slash}")
+                    } else {
+                        format!("/{}/{path_without_trailing_slash}", self.prefix)
+                    };
+                    if let Some(security) = self.security {
+                        security.apply(&mut headers);
+                    }
                     headers.push(Line::with_owned_value(LOCATION, location.into_bytes()));
-                    return request.response(StatusCode::PermanentRedirect, headers.iter(), None);
+                    return request.response(
+                        StatusCode::PermanentRedirect,
+                        headers.iter().chain(cors_headers.iter()),
+                        None,
+                    );
                 } else {
                     return request.response(
                         StatusCode::NotFound,
-                        self.header_selector.error_headers().iter(),
+                        self.header_selector
+                            .error_headers()
+                            .iter()
+                            .chain(cors_headers.iter()),
                         None::<&[u8]>,
                     );
                 }
             }
-            if path.starts_with('.') || path.contains("/.") {
+            if hidden(path, self.dot_paths) || escapes_root(path) {
                 return request.response(
                     StatusCode::NotFound,
-                    self.header_selector.error_headers().iter(),
+                    self.header_selector
+                        .error_headers()
+                        .iter()
+                        .chain(cors_headers.iter()),
                     None::<&[u8]>,
                 );
             }
@@ -81,16 +162,20 @@ impl<T: HeaderSelector> Handler<T> {
             for path in candidates {
                 let filename = filename(&path);
                 let extension = extension(filename);
+                let (selected, sniffed) = match self.header_selector.headers_for_path(&path) {
+                    Some(it) => (Some(it), false),
+                    None if self.sniff => (self.sniffed_headers(&path).await, true),
+                    None => (None, false),
+                };
                 if let Some(HeadersAndCompression {
                     mut headers,
                     compressible,
                     redirection,
-                }) = self
-                    .header_selector
-                    .headers_for_extension(filename, extension)
+                }) = selected
                 {
                     let meta = if compressible {
-                        if let Ok(mut file) = File::open(format!("{path}.br")).await {
+                        if let Ok(mut file) = File::open(self.root.join(format!("{path}.br"))).await
+                        {
                             let len = file
                                 .metadata()
                                 .await
@@ -113,7 +198,7 @@ impl<T: HeaderSelector> Handler<T> {
                     let meta = match meta {
                         Some(it) => Some(it),
                         None => {
-                            if let Ok(mut file) = File::open(&path).await {
+                            if let Ok(mut file) = File::open(self.root.join(&path)).await {
                                 let len = file
                                     .metadata()
                                     .await
@@ -133,6 +218,12 @@ impl<T: HeaderSelector> Handler<T> {
                         }
                     };
                     if let Some((compressed, etag, content)) = meta {
+                        if sniffed {
+                            request.sniffed(&path);
+                        }
+                        if let Some(security) = self.security {
+                            security.apply(&mut headers);
+                        }
                         if redirection {
                             headers.push(Line::with_slice_value(CONTENT_LENGTH, b"0"));
                             let end = content
@@ -141,6 +232,31 @@ impl<T: HeaderSelector> Handler<T> {
                                 .unwrap_or(content.len());
                             headers.push(Line::with_owned_value(LOCATION, content[..end].into()));
                         } else {
+                            if let Some(charsets) = self.charsets
+                                && let Some(line) =
+                                    headers.iter_mut().find(|it| it.key == CONTENT_TYPE)
+                            {
+                                // the detection looks at the decoded content of a .br sibling
+                                let decoded = if compressed && charsets.detect {
+                                    let mut decoded = vec![];
+                                    brotli::Decompressor::new(content.as_slice(), 4096)
+                                        .read_to_end(&mut decoded)
+                                        .ok()
+                                        .map(|_| decoded)
+                                } else {
+                                    None
+                                };
+                                let decoded = if compressed {
+                                    decoded.as_deref()
+                                } else {
+                                    Some(content.as_slice())
+                                };
+                                if let Some(value) =
+                                    charsets.content_type(line.value.as_ref(), decoded)
+                                {
+                                    *line = Line::with_owned_value(CONTENT_TYPE, value);
+                                }
+                            }
                             headers.push(Line::with_owned_value(
                                 CONTENT_LENGTH,
                                 format!("{}", content.len()).into_bytes(),
@@ -158,13 +274,13 @@ impl<T: HeaderSelector> Handler<T> {
                             if none_match.is_some() && none_match == etag {
                                 return request.response(
                                     StatusCode::NotModified,
-                                    headers.iter(),
+                                    headers.iter().chain(cors_headers.iter()),
                                     None::<&[u8]>,
                                 );
                             } else if if_match.is_some() && if_match != etag {
                                 return request.response(
                                     StatusCode::PreconditionFailed,
-                                    headers.iter(),
+                                    headers.iter().chain(cors_headers.iter()),
                                     None::<&[u8]>,
                                 );
                             }
@@ -173,20 +289,20 @@ impl<T: HeaderSelector> Handler<T> {
                             if etag.is_some() {
                                 request.response(
                                     StatusCode::TemporaryRedirect,
-                                    headers.iter(),
+                                    headers.iter().chain(cors_headers.iter()),
                                     None,
                                 )
                             } else {
                                 request.response(
                                     StatusCode::PermanentRedirect,
-                                    headers.iter(),
+                                    headers.iter().chain(cors_headers.iter()),
                                     None,
                                 )
                             }
                         } else {
                             request.response(
                                 StatusCode::OK,
-                                headers.iter(),
+                                headers.iter().chain(cors_headers.iter()),
                                 if is_get {
                                     Some(content.as_slice())
                                 } else {
@@ -200,10 +316,44 @@ impl<T: HeaderSelector> Handler<T> {
         }
         request.response(
             StatusCode::NotFound,
-            self.header_selector.error_headers().iter(),
+            self.header_selector
+                .error_headers()
+                .iter()
+                .chain(cors_headers.iter()),
             None::<&[u8]>,
         )
     }
+
+    // the headers for the format recognised from the first bytes of a file
+    async fn sniffed_headers(&self, path: &str) -> Option<HeadersAndCompression> {
+        let file = File::open(self.root.join(path)).await.ok()?;
+        let mut buf = Vec::with_capacity(SNIFF_LENGTH as usize);
+        file.take(SNIFF_LENGTH).read_to_end(&mut buf).await.ok()?;
+        self.header_selector.headers_for_content(&buf)
+    }
+
+    /// The file a request path points to when it exists but its extension has no headers,
+    /// which is why it got a 404.
+    pub async fn unmapped_file(&self, path: &[u8]) -> Option<String> {
+        let path = String::from_utf8_lossy(path);
+        let path = path.strip_prefix('/').unwrap_or(&path);
+        let path = path.strip_prefix(self.prefix)?;
+        let path = path.strip_prefix('/').unwrap_or(path);
+        if path.is_empty()
+            || path.ends_with('/')
+            || hidden(path, self.dot_paths)
+            || escapes_root(path)
+        {
+            return None;
+        }
+        if self.header_selector.headers_for_path(path).is_some() {
+            return None;
+        }
+        match tokio::fs::metadata(self.root.join(path)).await {
+            Ok(metadata) if metadata.is_file() => Some(path.to_string()),
+            _ => None,
+        }
+    }
 }
 
 pub trait HeaderSelector {
@@ -212,6 +362,15 @@ pub trait HeaderSelector {
         filename: &str,
         extension: &str,
     ) -> Option<HeadersAndCompression>;
+    /// Headers for a file from its path relative to the root, by default from its extension
+    fn headers_for_path(&self, path: &str) -> Option<HeadersAndCompression> {
+        let filename = filename(path);
+        self.headers_for_extension(filename, extension(filename))
+    }
+    /// Headers for a file without a known extension, from its first bytes
+    fn headers_for_content(&self, _content: &[u8]) -> Option<HeadersAndCompression> {
+        None
+    }
     fn error_headers(&self) -> &'static [Line];
 }
 
@@ -0,0 +1,426 @@
+use crate::log::iso_time;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use http_body_util::BodyExt;
+use http_body_util::combinators::BoxBody;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::header::{CONTENT_ENCODING, CONTENT_TYPE, HOST, LOCATION};
+use hyper::{HeaderMap, Request, Response, Version};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime};
+
+pub struct Recorder {
+    path: PathBuf,
+    bodies: bool,
+    entries: Mutex<Vec<Entry>>,
+}
+
+pub struct PendingRequest {
+    time: SystemTime,
+    started: Instant,
+    request: HarRequest,
+    body: Arc<Mutex<Option<CapturedBody>>>,
+}
+
+pub(crate) struct CapturedBody {
+    pub(crate) size: u64,
+    pub(crate) content: Option<Vec<u8>>,
+}
+
+impl Recorder {
+    pub fn new(path: PathBuf, bodies: bool) -> Self {
+        Self {
+            path,
+            bodies,
+            entries: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn record_request(
+        &'static self,
+        request: Request<BoxBody<Bytes, hyper::Error>>,
+    ) -> (Request<BoxBody<Bytes, hyper::Error>>, PendingRequest) {
+        let (parts, body) = request.into_parts();
+        let host = parts
+            .headers
+            .get(HOST)
+            .and_then(|it| it.to_str().ok())
+            .unwrap_or("localhost");
+        let path_and_query = parts
+            .uri
+            .path_and_query()
+            .map(|it| it.as_str())
+            .unwrap_or("/");
+        let request = HarRequest {
+            method: parts.method.to_string(),
+            url: format!("https://{host}{path_and_query}"),
+            http_version: version(parts.version),
+            cookies: vec![],
+            headers: headers(&parts.headers),
+            query_string: parts
+                .uri
+                .query()
+                .map(|query| {
+                    query
+                        .split('&')
+                        .filter(|it| !it.is_empty())
+                        .map(|it| {
+                            let (name, value) = it.split_once('=').unwrap_or((it, ""));
+                            NameValue {
+                                name: name.to_string(),
+                                value: value.to_string(),
+                            }
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            post_data: None,
+            headers_size: -1,
+            body_size: 0,
+        };
+        let captured = Arc::new(Mutex::new(None));
+        let body = CaptureBody::new(body, self.bodies, {
+            let captured = captured.clone();
+            move |it| {
+                if let Ok(mut captured) = captured.lock() {
+                    *captured = Some(it);
+                }
+            }
+        });
+        let pending = PendingRequest {
+            time: SystemTime::now(),
+            started: Instant::now(),
+            request,
+            body: captured,
+        };
+        (Request::from_parts(parts, body.boxed()), pending)
+    }
+
+    pub fn record_response(
+        &'static self,
+        pending: PendingRequest,
+        response: Response<BoxBody<Bytes, hyper::Error>>,
+        forwarded: bool,
+    ) -> Response<BoxBody<Bytes, hyper::Error>> {
+        let wait = pending.started.elapsed();
+        let (parts, body) = response.into_parts();
+        let header = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|it: &hyper::header::HeaderValue| it.to_str().ok())
+                .map(|it| it.to_string())
+        };
+        let encoding = header(CONTENT_ENCODING);
+        let response = HarResponse {
+            status: parts.status.as_u16(),
+            status_text: parts.status.canonical_reason().unwrap_or("").to_string(),
+            http_version: version(parts.version),
+            cookies: vec![],
+            headers: headers(&parts.headers),
+            content: Content {
+                size: 0,
+                mime_type: header(CONTENT_TYPE).unwrap_or_default(),
+                text: None,
+                encoding: None,
+                compression: None,
+            },
+            redirect_url: header(LOCATION).unwrap_or_default(),
+            headers_size: -1,
+            body_size: 0,
+        };
+        let PendingRequest {
+            time,
+            started,
+            mut request,
+            body: request_body,
+        } = pending;
+        let body = CaptureBody::new(body, self.bodies, move |captured| {
+            if let Some(CapturedBody { size, content }) =
+                request_body.lock().ok().and_then(|mut it| it.take())
+            {
+                request.body_size = size as i64;
+                if let Some(content) = content
+                    && !content.is_empty()
+                {
+                    request.post_data = Some(PostData {
+                        mime_type: request
+                            .headers
+                            .iter()
+                            .find(|it| it.name.eq_ignore_ascii_case("content-type"))
+                            .map(|it| it.value.clone())
+                            .unwrap_or_default(),
+                        text: String::from_utf8_lossy(&content).into_owned(),
+                    });
+                }
+            }
+            let mut response = response;
+            response.body_size = captured.size as i64;
+            response.content.size = captured.size as i64;
+            if let Some(content) = captured.content {
+                // the content is the decoded body, with the bytes saved as its compression
+                let (content, decoded) = match encoding.as_deref() {
+                    None => (content, true),
+                    Some(encoding) if encoding.eq_ignore_ascii_case("br") => {
+                        let mut decoded = vec![];
+                        match brotli::Decompressor::new(content.as_slice(), 4096)
+                            .read_to_end(&mut decoded)
+                        {
+                            Ok(_) => {
+                                response.content.size = decoded.len() as i64;
+                                response.content.compression =
+                                    Some(decoded.len() as i64 - captured.size as i64);
+                                (decoded, true)
+                            }
+                            Err(_) => (content, false),
+                        }
+                    }
+                    Some(_) => (content, false),
+                };
+                match String::from_utf8(content) {
+                    Ok(text) if decoded => response.content.text = Some(text),
+                    Ok(text) => {
+                        response.content.text = Some(STANDARD.encode(text));
+                        response.content.encoding = Some("base64");
+                    }
+                    Err(err) => {
+                        response.content.text = Some(STANDARD.encode(err.into_bytes()));
+                        response.content.encoding = Some("base64");
+                    }
+                }
+            }
+            let total = started.elapsed();
+            let entry = Entry {
+                started_date_time: iso_time(time),
+                time: millis(total),
+                request,
+                response,
+                cache: Cache {},
+                timings: Timings {
+                    send: 0.0,
+                    wait: millis(wait),
+                    receive: millis(total.saturating_sub(wait)),
+                },
+                comment: if forwarded { "forwarded" } else { "static" },
+            };
+            if let Ok(mut entries) = self.entries.lock() {
+                entries.push(entry);
+            }
+        });
+        Response::from_parts(parts, body.boxed())
+    }
+
+    pub fn write(&self) -> std::io::Result<usize> {
+        let entries = self
+            .entries
+            .lock()
+            .map_err(|_| std::io::Error::other("poisoned"))?;
+        let har = Har {
+            log: Log {
+                version: "1.2",
+                creator: Creator {
+                    name: env!("CARGO_PKG_NAME"),
+                    version: env!("CARGO_PKG_VERSION"),
+                },
+                entries: &entries,
+            },
+        };
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        serde_json::to_writer_pretty(&mut writer, &har)?;
+        writer.flush()?;
+        Ok(entries.len())
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+fn version(version: Version) -> String {
+    format!("{version:?}")
+}
+
+fn headers(headers: &HeaderMap) -> Vec<NameValue> {
+    headers
+        .iter()
+        .map(|(name, value)| NameValue {
+            name: name.to_string(),
+            value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+        })
+        .collect()
+}
+
+fn millis(duration: std::time::Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// counts (and optionally keeps) the bytes going through, and reports them once the body is done
+pub(crate) struct CaptureBody<F: FnOnce(CapturedBody) + Send + Sync + Unpin + 'static> {
+    inner: BoxBody<Bytes, hyper::Error>,
+    captured: CapturedBody,
+    on_end: Option<F>,
+}
+
+impl<F: FnOnce(CapturedBody) + Send + Sync + Unpin + 'static> CaptureBody<F> {
+    pub(crate) fn new(inner: BoxBody<Bytes, hyper::Error>, keep_content: bool, on_end: F) -> Self {
+        Self {
+            inner,
+            captured: CapturedBody {
+                size: 0,
+                content: if keep_content { Some(vec![]) } else { None },
+            },
+            on_end: Some(on_end),
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(on_end) = self.on_end.take() {
+            on_end(CapturedBody {
+                size: self.captured.size,
+                content: self.captured.content.take(),
+            });
+        }
+    }
+}
+
+impl<F: FnOnce(CapturedBody) + Send + Sync + Unpin + 'static> Body for CaptureBody<F> {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_frame(cx);
+        match &result {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    this.captured.size += data.len() as u64;
+                    if let Some(content) = this.captured.content.as_mut() {
+                        content.extend_from_slice(data);
+                    }
+                }
+            }
+            Poll::Ready(None) => this.finish(),
+            _ => {}
+        }
+        result
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<F: FnOnce(CapturedBody) + Send + Sync + Unpin + 'static> Drop for CaptureBody<F> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[derive(Serialize)]
+struct Har<'a> {
+    log: Log<'a>,
+}
+
+#[derive(Serialize)]
+struct Log<'a> {
+    version: &'static str,
+    creator: Creator,
+    entries: &'a [Entry],
+}
+
+#[derive(Serialize)]
+struct Creator {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    started_date_time: String,
+    time: f64,
+    request: HarRequest,
+    response: HarResponse,
+    cache: Cache,
+    timings: Timings,
+    comment: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HarRequest {
+    method: String,
+    url: String,
+    http_version: String,
+    cookies: Vec<NameValue>,
+    headers: Vec<NameValue>,
+    query_string: Vec<NameValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    post_data: Option<PostData>,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PostData {
+    mime_type: String,
+    text: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HarResponse {
+    status: u16,
+    status_text: String,
+    http_version: String,
+    cookies: Vec<NameValue>,
+    headers: Vec<NameValue>,
+    content: Content,
+    #[serde(rename = "redirectURL")]
+    redirect_url: String,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Content {
+    size: i64,
+    mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct NameValue {
+    name: String,
+    value: String,
+}
+
+#[derive(Serialize)]
+struct Cache {}
+
+#[derive(Serialize)]
+struct Timings {
+    send: f64,
+    wait: f64,
+    receive: f64,
+}
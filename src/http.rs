@@ -25,6 +25,13 @@ impl AsRef<[u8]> for OwnedOrStatic {
 pub mod headers {
     use crate::http::OwnedOrStatic;
 
+    pub const ACCESS_CONTROL_ALLOW_CREDENTIALS: &[u8] = b"access-control-allow-credentials";
+    pub const ACCESS_CONTROL_ALLOW_HEADERS: &[u8] = b"access-control-allow-headers";
+    pub const ACCESS_CONTROL_ALLOW_METHODS: &[u8] = b"access-control-allow-methods";
+    pub const ACCESS_CONTROL_ALLOW_ORIGIN: &[u8] = b"access-control-allow-origin";
+    pub const ACCESS_CONTROL_MAX_AGE: &[u8] = b"access-control-max-age";
+    pub const ACCESS_CONTROL_REQUEST_HEADERS: &[u8] = b"access-control-request-headers";
+    pub const ACCESS_CONTROL_REQUEST_METHOD: &[u8] = b"access-control-request-method";
     pub const ALLOW: &[u8] = b"allow";
     pub const CACHE_CONTROL: &[u8] = b"cache-control";
     pub const CONTENT_ENCODING: &[u8] = b"content-encoding";
@@ -34,12 +41,16 @@ pub mod headers {
     pub const COOP: &[u8] = b"cross-origin-opener-policy";
     pub const CORP: &[u8] = b"cross-origin-resource-policy";
     pub const CSP: &[u8] = b"content-security-policy";
+    pub const CSP_REPORT_ONLY: &[u8] = b"content-security-policy-report-only";
     pub const ETAG: &[u8] = b"etag";
     pub const IF_MATCH: &[u8] = b"if-match";
     pub const IF_NONE_MATCH: &[u8] = b"if-none-match";
     pub const LOCATION: &[u8] = b"location";
+    pub const ORIGIN: &[u8] = b"origin";
     pub const HSTS: &[u8] = b"strict-transport-security";
     pub const SERVICE_WORKER_ALLOWED: &[u8] = b"service-worker-allowed";
+    pub const TRANSFER_ENCODING: &[u8] = b"transfer-encoding";
+    pub const VARY: &[u8] = b"vary";
     // pub const WWW_AUTHENTICATE: &[u8] = b"www-authenticate";
     pub const X_CONTENT_TYPE_OPTIONS: &[u8] = b"x-content-type-options";
     pub const X_FRAME_OPTIONS: &[u8] = b"x-frame-options";
@@ -150,7 +161,7 @@ pub mod response {
 pub mod method {
     pub const HEAD: &[u8] = b"HEAD";
     pub const GET: &[u8] = b"GET";
-    // pub const OPTIONS: &[u8] = b"OPTIONS";
+    pub const OPTIONS: &[u8] = b"OPTIONS";
 }
 
 pub mod request {
@@ -167,5 +178,7 @@ pub mod request {
             headers: impl Iterator<Item = &'b Line>,
             body: Option<&'b [u8]>,
         ) -> R;
+        /// Called before the response when the type of the served file was sniffed
+        fn sniffed(&mut self, _path: &str) {}
     }
 }
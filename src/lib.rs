@@ -0,0 +1,18 @@
+pub mod adapter;
+pub mod archive;
+pub mod charset;
+pub mod check;
+pub mod cors;
+pub mod dump;
+pub mod forward;
+pub mod handler;
+pub mod har;
+pub mod http;
+pub mod lint;
+pub mod log;
+mod path;
+pub mod security;
+mod server;
+pub mod types;
+
+pub use server::{Server, ServerBuilder, ServerHandle};
@@ -0,0 +1,277 @@
+use crate::archive::redirect_location;
+use crate::check::{CheckRequest, Checked, handler};
+use crate::handler::Handler;
+use crate::path::{self, extension, filename};
+use crate::types::{DefaultHeaderSelector, headers_for_path};
+use std::collections::{BTreeMap, HashSet};
+use std::io::Read;
+use std::path::Path;
+
+pub struct Issue {
+    pub file: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Looks for what would break once served: links and redirects to missing paths,
+/// redirect chains and loops, files with an unknown extension and stale `.br` files.
+pub async fn lint(root: &Path, prefix: &str, dot_paths: &[&str]) -> std::io::Result<Vec<Issue>> {
+    let prefix = prefix.trim_matches('/');
+    let handler = handler(root, prefix, dot_paths);
+    let base = if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("/{prefix}")
+    };
+    let mut files = vec![];
+    path::files(root, &mut files)?;
+    let mut issues = vec![];
+    // resolved once per path, as many pages share the same links
+    let mut resolved = BTreeMap::<String, Checked>::new();
+    for file in files.iter() {
+        let Some(relative) = path::relative(root, file) else {
+            continue;
+        };
+        if path::hidden(&relative, dot_paths) {
+            continue;
+        }
+        let issue = |line, message| Issue {
+            file: relative.clone(),
+            line,
+            message,
+        };
+        if let Some(original) = relative.strip_suffix(".br") {
+            let Ok(expected) = std::fs::read(root.join(original)) else {
+                continue;
+            };
+            if !headers_for_path(original).is_some_and(|it| it.compressible) {
+                issues.push(issue(
+                    None,
+                    format!("never served, {original} is not a compressible type"),
+                ));
+                continue;
+            }
+            let mut decompressed = vec![];
+            let compressed = std::fs::read(file)?;
+            match brotli::Decompressor::new(compressed.as_slice(), 4096)
+                .read_to_end(&mut decompressed)
+            {
+                Ok(_) if decompressed == expected => {}
+                Ok(_) => issues.push(issue(None, format!("does not match {original}"))),
+                Err(err) => issues.push(issue(None, format!("invalid brotli: {err}"))),
+            }
+            continue;
+        }
+        let filename = filename(&relative);
+        let extension = extension(filename);
+        let Some(headers) = headers_for_path(&relative) else {
+            issues.push(issue(
+                None,
+                "unknown extension, the file is never served".to_string(),
+            ));
+            continue;
+        };
+        let stem = &relative[..relative.len() - extension.len() - 1];
+        if headers.redirection {
+            let content = std::fs::read(file)?;
+            if let Err(err) = redirect_location(&content) {
+                issues.push(issue(None, err));
+                continue;
+            }
+            let from = if stem == "index" {
+                format!("{base}/")
+            } else {
+                format!("{base}/{stem}")
+            };
+            if let Some(message) = follow(&handler, from, &mut resolved).await {
+                issues.push(issue(None, message));
+            }
+        } else if extension == "html" || extension == "htm" {
+            let html = String::from_utf8_lossy(&std::fs::read(file)?).into_owned();
+            let directory = match stem.rfind('/') {
+                Some(position) => format!("{base}/{}/", &stem[..position]),
+                None => format!("{base}/"),
+            };
+            for (line, link) in links(&html) {
+                let Some(target) = resolve(&directory, &link) else {
+                    continue;
+                };
+                if landing_status(&handler, target, &mut resolved).await == 404 {
+                    issues.push(issue(Some(line), format!("broken link to {link}")));
+                }
+            }
+        }
+    }
+    issues.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+    Ok(issues)
+}
+
+async fn status(
+    handler: &Handler<DefaultHeaderSelector>,
+    path: &str,
+    resolved: &mut BTreeMap<String, Checked>,
+) -> (u16, Option<String>) {
+    if !resolved.contains_key(path) {
+        let checked = handler
+            .handle(CheckRequest {
+                path: path.to_string(),
+            })
+            .await;
+        resolved.insert(path.to_string(), checked);
+    }
+    let checked = &resolved[path];
+    (
+        checked.status,
+        checked.header("location").map(|it| it.to_string()),
+    )
+}
+
+// the status after following local redirects, loops are reported with the redirect files
+async fn landing_status(
+    handler: &Handler<DefaultHeaderSelector>,
+    mut path: String,
+    resolved: &mut BTreeMap<String, Checked>,
+) -> u16 {
+    for _ in 0..10 {
+        match status(handler, &path, resolved).await {
+            (307 | 308, Some(location)) => match resolve("/", &location) {
+                Some(next) => path = next,
+                None => return 200,
+            },
+            (code, _) => return code,
+        }
+    }
+    200
+}
+
+// follows local redirects from a path, describing any chain, loop or missing target
+async fn follow(
+    handler: &Handler<DefaultHeaderSelector>,
+    from: String,
+    resolved: &mut BTreeMap<String, Checked>,
+) -> Option<String> {
+    let mut hops = vec![from.clone()];
+    let mut visited = HashSet::from([from.clone()]);
+    let mut current = from;
+    loop {
+        let (code, location) = status(handler, &current, resolved).await;
+        match (code, location) {
+            (307 | 308, Some(location)) => {
+                let Some(next) = resolve("/", &location) else {
+                    // leaves the site
+                    break;
+                };
+                hops.push(location);
+                if !visited.insert(next.clone()) {
+                    return Some(format!("redirect loop {}", hops.join(" -> ")));
+                }
+                current = next;
+            }
+            (404, _) if hops.len() > 1 => {
+                return Some(format!("redirect to a missing path {}", hops.join(" -> ")));
+            }
+            (_, _) if hops.len() == 1 => {
+                return Some(format!("never used, {} is served by another file", hops[0]));
+            }
+            (_, _) => break,
+        }
+    }
+    if hops.len() > 2 {
+        Some(format!("redirect chain {}", hops.join(" -> ")))
+    } else {
+        None
+    }
+}
+
+/// The `href` and `src` attribute values of an HTML document, with their line number.
+pub fn links(html: &str) -> Vec<(usize, String)> {
+    let bytes = html.as_bytes();
+    let mut links = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let name_length = if bytes[i..].len() >= 4 && bytes[i..i + 4].eq_ignore_ascii_case(b"href")
+        {
+            4
+        } else if bytes[i..].len() >= 3 && bytes[i..i + 3].eq_ignore_ascii_case(b"src") {
+            3
+        } else {
+            i += 1;
+            continue;
+        };
+        let start = i;
+        i += name_length;
+        // an attribute name, not part of a longer word
+        if start == 0 || !bytes[start - 1].is_ascii_whitespace() {
+            continue;
+        }
+        let mut j = i;
+        while j < bytes.len() && bytes[j].is_ascii_whitespace() {
+            j += 1;
+        }
+        if bytes.get(j) != Some(&b'=') {
+            continue;
+        }
+        j += 1;
+        while j < bytes.len() && bytes[j].is_ascii_whitespace() {
+            j += 1;
+        }
+        let (value_start, value_end) = match bytes.get(j) {
+            Some(&quote) if quote == b'"' || quote == b'\'' => {
+                let end = bytes[j + 1..]
+                    .iter()
+                    .position(|&it| it == quote)
+                    .map(|it| j + 1 + it)
+                    .unwrap_or(bytes.len());
+                (j + 1, end)
+            }
+            Some(_) => {
+                let end = bytes[j..]
+                    .iter()
+                    .position(|&it| it.is_ascii_whitespace() || it == b'>')
+                    .map(|it| j + it)
+                    .unwrap_or(bytes.len());
+                (j, end)
+            }
+            None => break,
+        };
+        let line = bytes[..start].iter().filter(|&&it| it == b'\n').count() + 1;
+        let value = html[value_start..value_end].trim().replace("&amp;", "&");
+        links.push((line, value));
+        i = value_end;
+    }
+    links
+}
+
+// the absolute path of a local link, relative to a directory ending with `/`
+fn resolve(directory: &str, link: &str) -> Option<String> {
+    let link = link.split(['#', '?']).next().unwrap_or("");
+    if link.is_empty() || link.starts_with("//") {
+        return None;
+    }
+    // any scheme (https:, mailto:, data:...) points outside the site
+    if let Some(colon) = link.find(':')
+        && !link[..colon].contains('/')
+    {
+        return None;
+    }
+    let joined = if link.starts_with('/') {
+        link.to_string()
+    } else {
+        format!("{directory}{link}")
+    };
+    let mut segments: Vec<&str> = vec![];
+    for segment in joined.split('/').skip(1) {
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            it => segments.push(it),
+        }
+    }
+    let mut path = format!("/{}", segments.join("/"));
+    if (joined.ends_with("/.") || joined.ends_with("/..")) && !path.ends_with('/') {
+        path.push('/');
+    }
+    Some(path)
+}
@@ -0,0 +1,260 @@
+use clap::ValueEnum;
+use colored::{ColoredString, Colorize};
+use hyper::StatusCode;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Common,
+    Combined,
+    Json,
+}
+
+#[derive(Serialize)]
+pub struct Entry {
+    #[serde(serialize_with = "serialize_time")]
+    pub time: SystemTime,
+    pub remote_address: SocketAddr,
+    pub method: String,
+    pub path: String,
+    pub version: String,
+    pub status: u16,
+    // body bytes actually sent
+    pub bytes: u64,
+    // until the end of the body
+    #[serde(rename = "duration_ms", serialize_with = "serialize_duration")]
+    pub duration: Duration,
+    pub content_encoding: Option<String>,
+    // hit (304) or miss (200) for conditional requests
+    pub cache: Option<&'static str>,
+    pub forwarded: bool,
+    #[serde(
+        rename = "upstream_duration_ms",
+        serialize_with = "serialize_optional_duration"
+    )]
+    pub upstream_duration: Option<Duration>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+pub struct AccessLog {
+    format: LogFormat,
+    file: Option<Mutex<File>>,
+    requests: AtomicU64,
+    forwarded: AtomicU64,
+    errors: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl AccessLog {
+    pub fn new(format: LogFormat, path: Option<&Path>) -> std::io::Result<Self> {
+        let file = match path {
+            Some(path) => Some(Mutex::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            None => None,
+        };
+        Ok(Self {
+            format,
+            file,
+            requests: AtomicU64::new(0),
+            forwarded: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        })
+    }
+
+    pub fn summary(&self, uptime: Duration) -> String {
+        format!(
+            "{} requests ({} forwarded, {} errors), {} bytes sent in {}s",
+            self.requests.load(Ordering::Relaxed),
+            self.forwarded.load(Ordering::Relaxed),
+            self.errors.load(Ordering::Relaxed),
+            self.bytes.load(Ordering::Relaxed),
+            uptime.as_secs()
+        )
+    }
+
+    pub fn log(&self, entry: &Entry) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if entry.forwarded {
+            self.forwarded.fetch_add(1, Ordering::Relaxed);
+        }
+        if entry.status >= 400 {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        self.bytes.fetch_add(entry.bytes, Ordering::Relaxed);
+        let colored = self.file.is_none();
+        let line = match self.format {
+            LogFormat::Pretty => pretty(entry, colored),
+            LogFormat::Common => common(entry),
+            LogFormat::Combined => combined(entry),
+            LogFormat::Json => serde_json::to_string(entry).unwrap_or_default(),
+        };
+        match self.file.as_ref() {
+            Some(file) => {
+                if let Ok(mut file) = file.lock() {
+                    let _ = writeln!(file, "{line}");
+                }
+            }
+            None => println!("{line}"),
+        }
+    }
+}
+
+fn pretty(entry: &Entry, colored: bool) -> String {
+    let status = StatusCode::from_u16(entry.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut details = format!("{}ms {}B", entry.duration.as_millis(), entry.bytes);
+    if let Some(encoding) = entry.content_encoding.as_ref() {
+        details.push_str(&format!(" {encoding}"));
+    }
+    if let Some(upstream_duration) = entry.upstream_duration {
+        details.push_str(&format!(" upstream {}ms", upstream_duration.as_millis()));
+    }
+    if colored {
+        format!(
+            "{} {} {} {} {}",
+            if entry.forwarded { ">>" } else { "  " },
+            status_string(&status),
+            method_string(entry.method.as_bytes()),
+            entry.path,
+            details.dimmed(),
+        )
+    } else {
+        format!(
+            "{} {} {} {} {details}",
+            if entry.forwarded { ">>" } else { "  " },
+            entry.status,
+            entry.method,
+            entry.path,
+        )
+    }
+}
+
+fn common(entry: &Entry) -> String {
+    format!(
+        "{} - - [{}] \"{} {} {}\" {} {}",
+        entry.remote_address.ip(),
+        clf_time(entry.time),
+        entry.method,
+        entry.path,
+        entry.version,
+        entry.status,
+        // no body is written as - in the common log format
+        if entry.bytes == 0 {
+            "-".to_string()
+        } else {
+            entry.bytes.to_string()
+        },
+    )
+}
+
+fn combined(entry: &Entry) -> String {
+    format!(
+        "{} \"{}\" \"{}\"",
+        common(entry),
+        entry.referer.as_deref().unwrap_or("-").escape_default(),
+        entry.user_agent.as_deref().unwrap_or("-").escape_default(),
+    )
+}
+
+pub fn method_string(method: &[u8]) -> ColoredString {
+    match method {
+        b"HEAD" => "HEAD".yellow(),
+        b"GET" => "GET".dimmed(),
+        b"OPTIONS" => "OPTIONS".cyan(),
+        _ => method.escape_ascii().to_string().red(),
+    }
+}
+
+pub fn status_string(status: &StatusCode) -> ColoredString {
+    if status.is_success() {
+        status.as_u16().to_string().green()
+    } else if status.is_redirection() {
+        if status == &StatusCode::NOT_MODIFIED {
+            status.as_u16().to_string().purple()
+        } else {
+            status.as_u16().to_string().blue()
+        }
+    } else if status.is_client_error() || status.is_server_error() {
+        status.as_u16().to_string().red()
+    } else {
+        status.as_u16().to_string().normal()
+    }
+}
+
+// (year, month, day, hours, minutes, seconds, milliseconds) in UTC
+fn utc(time: SystemTime) -> (i64, u32, u32, u64, u64, u64, u32) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let days = (seconds / 86400) as i64;
+    let seconds_of_day = seconds % 86400;
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis(),
+    )
+}
+
+fn clf_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (year, month, day, hours, minutes, seconds, _) = utc(time);
+    format!(
+        "{day:02}/{}/{year}:{hours:02}:{minutes:02}:{seconds:02} +0000",
+        MONTHS[month as usize - 1]
+    )
+}
+
+pub fn iso_time(time: SystemTime) -> String {
+    let (year, month, day, hours, minutes, seconds, millis) = utc(time);
+    format!("{year}-{month:02}-{day:02}T{hours:02}:{minutes:02}:{seconds:02}.{millis:03}Z")
+}
+
+fn serialize_time<S: serde::Serializer>(
+    time: &SystemTime,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&iso_time(*time))
+}
+
+fn serialize_duration<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+fn serialize_optional_duration<S: serde::Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serialize_duration(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
@@ -1,40 +1,27 @@
-mod adapter;
-mod handler;
-mod http;
-mod path;
-mod types;
-
-use crate::adapter::RequestAdapter;
-use crate::handler::Handler;
-use crate::types::DefaultHeaderSelector;
-use ::hyper::body::Bytes;
-use ::hyper::service::service_fn;
-use clap::Parser;
-use colored::{ColoredString, Colorize};
-use http_body_util::combinators::BoxBody;
-use http_body_util::{BodyExt, Empty};
-use hyper::body::Incoming;
-use hyper::server::conn::http1;
-use hyper::{Request, Response, StatusCode, Uri};
-use hyper_rustls::HttpsConnectorBuilder;
-use hyper_util::client::legacy::Client;
-use hyper_util::rt::{TokioExecutor, TokioIo};
-use rcgen::generate_simple_self_signed;
-use std::convert::Infallible;
-use std::net::{Ipv4Addr, Ipv6Addr};
-use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use colored::Colorize;
+use hyper::Uri;
+use serde::Serialize;
+use serve::Server;
+use serve::archive::build_zip;
+use serve::charset::{CharsetRule, Charsets};
+use serve::check::{CheckFormat, check, print, unmapped_files};
+use serve::cors::Cors;
+use serve::dump::Dump;
+use serve::forward::{
+    ForwardOptions, ForwardPolicy, Proxy, Route, StatusRule, Upstream, UpstreamTls,
+};
+use serve::handler;
+use serve::har::Recorder;
+use serve::lint::lint;
+use serve::log::{AccessLog, LogFormat};
+use serve::security::{CspPolicy, Isolation, Security};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
-use tokio::net::TcpListener;
+use std::time::Duration;
 use tokio::spawn;
-use tokio_rustls::rustls::client::danger::{
-    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
-};
-use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
-use tokio_rustls::rustls::{
-    ClientConfig, DigitallySignedStruct, Error, ServerConfig, SignatureScheme,
-};
-use tokio_rustls::{TlsAcceptor, rustls};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -42,289 +29,411 @@ name="serve",
 bin_name="serve",
 version,
 about = "HTTP server that serves the static content in the current directory.",
-long_about = None
+long_about = None,
+args_conflicts_with_subcommands = true
 )]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[arg(long)]
     prefix: Option<PathBuf>,
+    /// Port to listen on (443 by default, 0 picks a free one)
     #[arg(long)]
     // #[arg(default_value = "8443")]
     port: Option<u16>,
     #[arg(long)]
     // #[arg(default_value = "https://localhost")]
     forwarded_origin: Option<String>,
+    /// Whether static content or the forwarded origin is tried first
+    #[arg(long, value_enum, default_value_t)]
+    forward_policy: ForwardPolicy,
+    /// Static response status that is forwarded with static-first (e.g. 404 or 4xx, defaults to 4xx)
+    #[arg(long, value_delimiter = ',')]
+    forward_on: Vec<StatusRule>,
+    /// Upstream response status that falls back to static content with proxy-first (defaults to 404)
+    #[arg(long, value_delimiter = ',')]
+    fallback_on: Vec<StatusRule>,
+    /// Serve the static response when the forwarded origin also fails (4xx or 5xx)
+    #[arg(long)]
+    static_on_upstream_error: bool,
+    /// Send requests under a path prefix straight to an upstream (`<prefix>=<origin>[/<path>]`).
+    /// When the origin has a path, it replaces the prefix (`/api=https://localhost:8080/` strips it).
+    #[arg(long)]
+    proxy: Vec<Route>,
+    /// Skip certificate verification for upstreams
+    #[arg(long)]
+    upstream_insecure: bool,
+    /// Skip certificate verification for local upstreams only (localhost, 127.0.0.1 and ::1),
+    /// which usually have self-signed certificates
+    #[arg(long)]
+    upstream_insecure_localhost: bool,
+    /// PEM file with additional CA certificates trusted for upstreams
+    #[arg(long)]
+    upstream_ca: Vec<PathBuf>,
+    /// Send the upstream host instead of the dev server one in the Host header
+    #[arg(long)]
+    rewrite_host: bool,
+    /// Add the security headers sent with static content to proxied responses
+    #[arg(long)]
+    proxy_security_headers: bool,
+    /// Cross-origin embedder policy, credentialless or require-corp enable cross-origin isolation
+    #[arg(long, value_enum, default_value_t)]
+    isolation: Isolation,
+    /// Content security policy: a preset (default, strict, relaxed or none) or a policy where
+    /// {<preset>} expands to a preset (e.g. "{strict}; report-uri /csp")
+    #[arg(long)]
+    csp: Option<CspPolicy>,
+    /// Policy sent as Content-Security-Policy-Report-Only, to trial it without enforcing it
+    #[arg(long)]
+    csp_report_only: Option<CspPolicy>,
+    /// Origin allowed to make cross-origin requests (`*` for any)
+    #[arg(long = "cors-origin", value_delimiter = ',')]
+    cors_origins: Vec<String>,
+    /// Method allowed in preflight responses (defaults to GET, HEAD, OPTIONS)
+    #[arg(long = "cors-method", value_delimiter = ',')]
+    cors_methods: Vec<String>,
+    /// Header allowed in preflight responses (defaults to the requested headers)
+    #[arg(long = "cors-header", value_delimiter = ',')]
+    cors_headers: Vec<String>,
+    #[arg(long)]
+    cors_credentials: bool,
+    #[arg(long)]
+    cors_max_age: Option<u32>,
+    #[arg(long, value_enum, default_value_t)]
+    log_format: LogFormat,
+    /// Append the access log to a file instead of printing it
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+    /// Print the request and response headers of every request
+    #[arg(long, visible_alias = "verbose")]
+    dump_headers: bool,
+    /// Only dump the headers of requests with a path matching this glob (e.g. /assets/**)
+    #[arg(long)]
+    dump_path: Vec<String>,
+    /// Only dump the headers of responses with this status (e.g. 404 or 4xx)
+    #[arg(long, value_delimiter = ',')]
+    dump_status: Vec<StatusRule>,
+    /// Record every request and response and write them as a HAR file on exit (or on SIGUSR1)
+    #[arg(long)]
+    har: Option<PathBuf>,
+    /// Include the request and response bodies in the HAR file
+    #[arg(long)]
+    har_bodies: bool,
+    /// Write a JSON object with the url, port and certificate fingerprint once listening
+    #[arg(long)]
+    ready_file: Option<PathBuf>,
+    /// Print the same JSON object as a single line on stdout once listening
+    #[arg(long)]
+    ready_line: bool,
+    /// List the files that can't be served because their extension has no known type
+    #[arg(long)]
+    scan_unservable: bool,
+    /// Type files without a known extension from their first bytes (images, wasm, pdf, zip, json, html)
+    #[arg(long)]
+    sniff: bool,
+    /// Dotfile or dot directory served despite the dotfile rule, relative to the root
+    /// (defaults to .well-known)
+    #[arg(long = "dot-path", value_delimiter = ',')]
+    dot_paths: Vec<String>,
+    /// Charset of text content types (none to send no charset parameter)
+    #[arg(long, default_value = "utf-8")]
+    default_charset: String,
+    /// Charset of a content type, overriding the default (e.g. text/csv=windows-1252 or text/markdown=none)
+    #[arg(long, value_delimiter = ',')]
+    charset: Vec<CharsetRule>,
+    /// Set the charset from the BOM of text files, and drop utf-8 for files that are not valid UTF-8
+    #[arg(long)]
+    detect_charset: bool,
+    /// Seconds to wait for in-flight connections on shutdown
+    #[arg(long, default_value_t = 10)]
+    shutdown_timeout: u64,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the response served for every path of the site, to compare with production
+    Check {
+        /// Directory with the static content
+        #[arg(long, default_value = ".")]
+        root: PathBuf,
+        #[arg(long)]
+        prefix: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t)]
+        format: CheckFormat,
+        /// Dotfile or dot directory served despite the dotfile rule (defaults to .well-known)
+        #[arg(long = "dot-path", value_delimiter = ',')]
+        dot_paths: Vec<String>,
+    },
+    /// Report broken links, redirect chains and loops, unknown extensions and stale .br files
+    Lint {
+        /// Directory with the static content
+        #[arg(long, default_value = ".")]
+        root: PathBuf,
+        #[arg(long)]
+        prefix: Option<PathBuf>,
+        /// Dotfile or dot directory served despite the dotfile rule (defaults to .well-known)
+        #[arg(long = "dot-path", value_delimiter = ',')]
+        dot_paths: Vec<String>,
+    },
+    /// Pack the site into a zip for zip_static_handler, with brotli siblings
+    BuildZip {
+        /// Directory with the static content
+        #[arg(long, default_value = ".")]
+        root: PathBuf,
+        #[arg(long, default_value = "static.zip")]
+        output: PathBuf,
+        /// Brotli quality, from 0 to 11
+        #[arg(long, default_value_t = 11, value_parser = clap::value_parser!(u32).range(0..=11))]
+        quality: u32,
+        /// Dotfile or dot directory served despite the dotfile rule (defaults to .well-known)
+        #[arg(long = "dot-path", value_delimiter = ',')]
+        dot_paths: Vec<String>,
+    },
 }
+
 #[tokio::main]
 async fn main() {
     #[cfg(windows)]
     colored::control::set_virtual_terminal(true).ok();
     let args = Args::parse();
-    let prefix = args
-        .prefix
-        .map(|it| it.to_str().expect("invalid prefix").to_string())
-        .unwrap_or("/".to_string());
-    let prefix = prefix.strip_prefix('/').unwrap_or(&prefix).to_string();
-    let prefix = prefix.strip_suffix('/').unwrap_or(&prefix).to_string();
-    let prefix: &'static str = prefix.leak();
-    let domains: Vec<String> = vec![
-        format!("{}", Ipv4Addr::LOCALHOST),
-        format!("{}", Ipv6Addr::LOCALHOST),
-    ];
-    let cert = Box::leak(Box::new(
-        generate_simple_self_signed(domains)
-            .expect("failed to generate self-signed certificate for localhost"),
-    ));
-    let mut server_config = ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(
-            vec![cert.cert.der().clone()],
-            PrivateKeyDer::Pkcs8(cert.signing_key.serialized_der().into()),
-        )
-        .expect("Failed to create certificate.");
-    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
-    let tls_acceptor = TlsAcceptor::from(Arc::new(server_config));
-    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, args.port.unwrap_or(443u16)))
-        .await
-        .expect("Failed to bind to port 443");
-    println!(
-        "{}",
-        format!(
-            "https://localhost{}/{prefix}",
-            match args.port {
-                Some(port) if port != 443 => format!(":{}", port),
-                _ => "".to_string(),
-            }
-        )
-        .bright_red()
-        .underline()
-    );
-    let forwarded_uri = args
-        .forwarded_origin
-        .as_ref()
-        .map(|it| Arc::new(Uri::from_str(it).expect("invalid forwarded origin")));
-    let client = if args.forwarded_origin.is_some() {
-        #[derive(Debug)]
-        struct AcceptAllVerifier {
-            server_name: String,
+    match args.command {
+        Some(Command::Check {
+            root,
+            prefix,
+            format,
+            dot_paths,
+        }) => {
+            let prefix = prefix
+                .map(|it| it.to_str().expect("invalid prefix").to_string())
+                .unwrap_or_default();
+            let checked = check(&root, &prefix, &handler::dot_paths(&dot_paths))
+                .await
+                .expect("failed to read the site");
+            print(&checked, format);
+            return;
         }
-        impl ServerCertVerifier for AcceptAllVerifier {
-            fn verify_server_cert(
-                &self,
-                _end_entity: &CertificateDer<'_>,
-                _intermediates: &[CertificateDer<'_>],
-                server_name: &ServerName<'_>,
-                _ocsp_response: &[u8],
-                _now: UnixTime,
-            ) -> Result<ServerCertVerified, Error> {
-                let server_name = server_name.to_str();
-                if server_name == "127.0.0.1"
-                    || server_name == "::1"
-                    || server_name == "localhost"
-                    || server_name == self.server_name
-                {
-                    return Ok(ServerCertVerified::assertion());
-                }
-                Err(Error::General(format!("{server_name} not forwarded")))
+        Some(Command::Lint {
+            root,
+            prefix,
+            dot_paths,
+        }) => {
+            let prefix = prefix
+                .map(|it| it.to_str().expect("invalid prefix").to_string())
+                .unwrap_or_default();
+            let issues = lint(&root, &prefix, &handler::dot_paths(&dot_paths))
+                .await
+                .expect("failed to read the site");
+            for issue in issues.iter() {
+                let location = match issue.line {
+                    Some(line) => format!("{}:{line}", issue.file),
+                    None => issue.file.clone(),
+                };
+                println!("{} {}", format!("{location}:").red(), issue.message);
             }
-            fn verify_tls12_signature(
-                &self,
-                _message: &[u8],
-                _cert: &CertificateDer<'_>,
-                _dss: &DigitallySignedStruct,
-            ) -> Result<HandshakeSignatureValid, Error> {
-                Ok(HandshakeSignatureValid::assertion())
+            if !issues.is_empty() {
+                println!("{} issue(s)", issues.len());
+                std::process::exit(1);
             }
-            fn verify_tls13_signature(
-                &self,
-                _message: &[u8],
-                _cert: &CertificateDer<'_>,
-                _dss: &DigitallySignedStruct,
-            ) -> Result<HandshakeSignatureValid, Error> {
-                Ok(HandshakeSignatureValid::assertion())
-            }
-            fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-                vec![
-                    SignatureScheme::RSA_PKCS1_SHA256,
-                    SignatureScheme::ECDSA_NISTP256_SHA256,
-                    SignatureScheme::ED25519,
-                ]
+            return;
+        }
+        Some(Command::BuildZip {
+            root,
+            output,
+            quality,
+            dot_paths,
+        }) => {
+            match build_zip(&root, &output, quality, &handler::dot_paths(&dot_paths)) {
+                Ok(summary) => {
+                    for (path, reason) in summary.skipped {
+                        println!("{}", format!("skipped {path} ({reason})").dimmed());
+                    }
+                    for warning in summary.warnings {
+                        println!("{}", warning.yellow());
+                    }
+                    println!(
+                        "{} files ({} with brotli) written to {}",
+                        summary.files,
+                        summary.compressed,
+                        output.display()
+                    );
+                }
+                Err(err) => {
+                    println!("{}", err.red());
+                    std::process::exit(1);
+                }
             }
+            return;
+        }
+        None => {}
+    }
+    if args.scan_unservable {
+        let unmapped = unmapped_files(Path::new("."), &handler::dot_paths(&args.dot_paths))
+            .expect("failed to scan the directory");
+        for file in unmapped.iter() {
+            println!("{} {file}", "not servable (unknown extension):".yellow());
         }
-        let config = ClientConfig::builder_with_protocol_versions(&[&rustls::version::TLS13])
-            .dangerous()
-            .with_custom_certificate_verifier(Arc::new(AcceptAllVerifier {
-                server_name: forwarded_uri
-                    .as_ref()
-                    .unwrap()
-                    .host()
-                    .unwrap_or("localhost")
-                    .to_string(),
-            }))
-            .with_no_client_auth();
-        let client: Client<_, BoxBody<Bytes, hyper::Error>> = Client::builder(TokioExecutor::new())
-            .build(
-                HttpsConnectorBuilder::new()
-                    .with_tls_config(config)
-                    .https_only()
-                    .enable_all_versions()
-                    .build(),
-            );
-        Some(Arc::new(client))
+        if unmapped.is_empty() {
+            println!("{}", "every file has a known type".dimmed());
+        }
+    }
+    let port = args.port.unwrap_or(443u16);
+    let mut builder = Server::builder()
+        .address(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+        .forward_policy(args.forward_policy)
+        .forward_on(args.forward_on)
+        .fallback_on(args.fallback_on)
+        .static_on_upstream_error(args.static_on_upstream_error)
+        .sniff(args.sniff)
+        .dot_paths(args.dot_paths)
+        .access_log(
+            AccessLog::new(args.log_format, args.log_file.as_deref())
+                .expect("failed to open log file"),
+        );
+    if let Some(prefix) = args.prefix.as_ref() {
+        builder = builder.prefix(prefix.to_str().expect("invalid prefix"));
+    }
+    if !args.charset.is_empty() || args.detect_charset || args.default_charset != "utf-8" {
+        builder = builder.charsets(Charsets {
+            default: Some(args.default_charset)
+                .filter(|it| !it.eq_ignore_ascii_case("none"))
+                .map(|it| it.to_ascii_lowercase()),
+            rules: args.charset,
+            detect: args.detect_charset,
+        });
+    }
+    if !args.cors_origins.is_empty() {
+        builder = builder.cors(Cors {
+            origins: args.cors_origins,
+            methods: args.cors_methods,
+            headers: args.cors_headers,
+            credentials: args.cors_credentials,
+            max_age: args.cors_max_age,
+        });
+    }
+    let security = if args.isolation != Isolation::default()
+        || args.csp.is_some()
+        || args.csp_report_only.is_some()
+    {
+        let default = Security::default();
+        let security = Security {
+            isolation: args.isolation,
+            csp: args.csp.unwrap_or(default.csp),
+            csp_report_only: args.csp_report_only,
+        };
+        builder = builder.security(security.clone());
+        Some(security)
     } else {
         None
     };
-    loop {
-        if let Ok((tcp_stream, _remote_address)) = listener.accept().await {
-            let tls_acceptor = tls_acceptor.clone();
-            let client = client.clone();
-            let forwarded_uri = forwarded_uri.clone();
-            spawn(async move {
-                if let Ok(tls_stream) = tls_acceptor.accept(tcp_stream).await {
-                    let io = TokioIo::new(tls_stream);
-                    let client = client.clone();
-                    let forwarded_uri = forwarded_uri.clone();
-                    let _ = http1::Builder::new()
-                        .serve_connection(
-                            io,
-                            service_fn({
-                                let client = client.clone();
-                                let forwarded_uri = forwarded_uri.clone();
-                                move |request: Request<Incoming>| {
-                                    let client = client.clone();
-                                    let forwarded_uri = forwarded_uri.clone();
-                                    async move {
-                                        let header_selector = DefaultHeaderSelector;
-                                        let handler = Handler {
-                                            prefix,
-                                            header_selector,
-                                        };
-                                        let (parts, body) = request.into_parts();
-                                        let request =
-                                            Request::from_parts(parts.clone(), empty_body());
-                                        let request_message = format!(
-                                            "{} {}",
-                                            method_string(request.method().as_str().as_bytes()),
-                                            request
-                                                .uri()
-                                                .path_and_query()
-                                                .map(|it| it.as_str())
-                                                .unwrap_or("/")
-                                        );
-                                        let response =
-                                            handler.handle(RequestAdapter { inner: request }).await;
-                                        let (response, forwarded) = if response
-                                            .status()
-                                            .is_client_error()
-                                        {
-                                            let body = body.boxed();
-                                            if let Some(client) = client.as_ref() {
-                                                let mut request = Request::from_parts(parts, body);
-                                                let uri = request.uri();
-                                                let forward_uri = Uri::builder();
-                                                let forwarded_uri = forwarded_uri.unwrap();
-                                                let forward_uri = if let Some(scheme) =
-                                                    forwarded_uri.scheme().or_else(|| uri.scheme())
-                                                {
-                                                    forward_uri.scheme(scheme.clone())
-                                                } else {
-                                                    forward_uri
-                                                };
-                                                let forward_uri = if let Some(authority) =
-                                                    forwarded_uri
-                                                        .authority()
-                                                        .or_else(|| uri.authority())
-                                                {
-                                                    forward_uri.authority(authority.clone())
-                                                } else {
-                                                    forward_uri
-                                                };
-                                                let forward_uri = if let Some(path_and_query) =
-                                                    uri.path_and_query()
-                                                {
-                                                    forward_uri
-                                                        .path_and_query(path_and_query.clone())
-                                                } else {
-                                                    forward_uri
-                                                };
-                                                let forward_uri = forward_uri
-                                                    .build()
-                                                    .expect("could not build forwarded uri");
-                                                *request.uri_mut() = forward_uri;
-                                                let forward_response =
-                                                    client.request(request).await;
-                                                match forward_response {
-                                                    Ok(forward_response) => {
-                                                        let (parts, body) =
-                                                            forward_response.into_parts();
-                                                        (
-                                                            Response::from_parts(
-                                                                parts,
-                                                                body.boxed(),
-                                                            ),
-                                                            true,
-                                                        )
-                                                    }
-                                                    Err(err) => {
-                                                        println!(
-                                                            "{}\n{err:?}",
-                                                            "error on forwarded response".red()
-                                                        );
-                                                        (response, false)
-                                                    }
-                                                }
-                                            } else {
-                                                let _ = body.collect().await;
-                                                (response, false)
-                                            }
-                                        } else {
-                                            (response, false)
-                                        };
-                                        println!(
-                                            "{} {} {request_message}",
-                                            if forwarded { ">>" } else { "  " },
-                                            status_string(&response.status()),
-                                        );
-                                        Ok::<Response<BoxBody<Bytes, hyper::Error>>, Infallible>(
-                                            response,
-                                        )
-                                    }
-                                }
-                            }),
-                        )
-                        .await;
-                }
-            });
+    let forward_options = Arc::new(ForwardOptions {
+        tls: UpstreamTls::new(
+            args.upstream_insecure,
+            args.upstream_insecure_localhost,
+            &args.upstream_ca,
+        )
+        .expect("invalid upstream ca"),
+        rewrite_host: args.rewrite_host,
+        security_headers: args.proxy_security_headers,
+        security,
+    });
+    if let Some(origin) = args.forwarded_origin.as_ref() {
+        builder = builder.forwarded(Upstream::new(
+            Uri::from_str(origin).expect("invalid forwarded origin"),
+            forward_options.clone(),
+        ));
+    }
+    for route in args.proxy {
+        builder = builder.proxy(Proxy::new(route, forward_options.clone()));
+    }
+    if args.dump_headers || !args.dump_path.is_empty() || !args.dump_status.is_empty() {
+        builder = builder.dump(Dump {
+            paths: args.dump_path,
+            statuses: args.dump_status,
+        });
+    }
+    let har: Option<&'static Recorder> = args
+        .har
+        .map(|it| &*Box::leak(Box::new(Recorder::new(it, args.har_bodies))));
+    if let Some(har) = har {
+        builder = builder.har(har);
+        #[cfg(unix)]
+        spawn(save_har_on_user_signal(har));
+    }
+    let mut server = builder
+        .start()
+        .await
+        .unwrap_or_else(|err| panic!("Failed to bind to port {port}: {err}"));
+    println!("{}", server.url().bright_red().underline());
+    if args.ready_file.is_some() || args.ready_line {
+        let ready = Ready {
+            url: server.url(),
+            port: server.address().port(),
+            fingerprint: server.fingerprint(),
+        };
+        let json = serde_json::to_string(&ready).expect("failed to serialize ready signal");
+        if let Some(path) = args.ready_file.as_ref() {
+            // written next to the target and renamed, so a watcher never sees a partial file
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, &json)
+                .and_then(|_| std::fs::rename(&tmp, path))
+                .expect("failed to write ready file");
+        }
+        if args.ready_line {
+            println!("{json}");
         }
     }
+    shutdown_signal().await;
+    tokio::select! {
+        _ = server.shutdown(Duration::from_secs(args.shutdown_timeout)) => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    println!("{}", server.summary().dimmed());
+    if let Some(har) = har {
+        save_har(har);
+    }
 }
 
-fn empty_body() -> BoxBody<Bytes, hyper::Error> {
-    Empty::<Bytes>::new()
-        .map_err(|err: Infallible| match err {})
-        .boxed()
+#[derive(Serialize)]
+struct Ready<'a> {
+    url: &'a str,
+    port: u16,
+    // SHA-256 of the self-signed certificate, as colon separated hex
+    fingerprint: String,
 }
 
-fn method_string(method: &[u8]) -> ColoredString {
-    match method {
-        b"HEAD" => "HEAD".yellow(),
-        b"GET" => "GET".dimmed(),
-        b"OPTIONS" => "OPTIONS".cyan(),
-        _ => method.escape_ascii().to_string().red(),
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
     }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
 
-fn status_string(status: &StatusCode) -> ColoredString {
-    if status.is_success() {
-        status.as_u16().to_string().green()
-    } else if status.is_redirection() {
-        if status == &StatusCode::NOT_MODIFIED {
-            status.as_u16().to_string().purple()
-        } else {
-            status.as_u16().to_string().blue()
-        }
-    } else if status.is_client_error() || status.is_server_error() {
-        status.as_u16().to_string().red()
-    } else {
-        status.as_u16().to_string().normal()
+#[cfg(unix)]
+async fn save_har_on_user_signal(har: &'static Recorder) {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut user_signal =
+        signal(SignalKind::user_defined1()).expect("failed to listen for SIGUSR1");
+    while user_signal.recv().await.is_some() {
+        save_har(har);
+    }
+}
+
+fn save_har(har: &Recorder) {
+    match har.write() {
+        Ok(count) => println!(
+            "{}",
+            format!("{count} entries written to {}", har.path().display()).dimmed()
+        ),
+        Err(err) => println!(
+            "{} {err}",
+            format!("failed to write {}:", har.path().display()).red()
+        ),
     }
 }
@@ -1,3 +1,5 @@
+use std::path::{Component, Path, PathBuf};
+
 pub(crate) fn filename(name: &str) -> &str {
     let byte_position = name.rfind('/').map(|it| it + 1).unwrap_or(0);
     &name[byte_position..]
@@ -7,3 +9,53 @@ pub(crate) fn extension(filename: &str) -> &str {
     let byte_position = filename.rfind('.').map(|it| it + 1).unwrap_or(0);
     &filename[byte_position..]
 }
+
+// whether a relative path goes through a dotfile or a dot directory that is not allowed,
+// allowed ones being listed with their full relative path (`.` and `..` never are)
+pub(crate) fn hidden(path: &str, allowed: &[&str]) -> bool {
+    let mut length = 0;
+    for segment in path.split('/') {
+        length += segment.len();
+        if segment.starts_with('.')
+            && (segment == "." || segment == ".." || !allowed.contains(&&path[..length]))
+        {
+            return true;
+        }
+        length += 1;
+    }
+    false
+}
+
+// whether joining the path to the root could reach outside of it, as an absolute path
+// replaces the root instead of being appended to it
+pub(crate) fn escapes_root(path: &str) -> bool {
+    path.starts_with('/')
+        || Path::new(path).components().any(|it| {
+            matches!(
+                it,
+                Component::RootDir | Component::Prefix(_) | Component::ParentDir
+            )
+        })
+}
+
+// every file under the directory, recursively
+pub(crate) fn files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            self::files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// the path relative to the root, with `/` separators
+pub(crate) fn relative(root: &Path, path: &Path) -> Option<String> {
+    path.strip_prefix(root)
+        .ok()
+        .and_then(|it| it.to_str())
+        .map(|it| it.replace('\\', "/"))
+}
@@ -0,0 +1,180 @@
+use crate::http::headers::{COEP, CSP, CSP_REPORT_ONLY, Line};
+use clap::ValueEnum;
+use std::str::FromStr;
+
+pub(crate) const CSP_DEFAULT: &[u8] = b"\
+    default-src 'self';\
+    connect-src 'self' https: data: blob:;\
+    script-src 'wasm-unsafe-eval';\
+    script-src-elem 'self' 'unsafe-inline';\
+    script-src-attr 'none';\
+    worker-src 'self' blob:;\
+    style-src 'self' 'unsafe-inline';\
+    img-src 'self' data: blob:;\
+    font-src 'self' data:;\
+    frame-src 'none';\
+    object-src 'none';\
+    base-uri 'none';\
+    frame-ancestors 'none';\
+    form-action 'none'\
+";
+// no inline scripts or styles, only same-origin resources
+const CSP_STRICT: &[u8] = b"\
+    default-src 'self';\
+    script-src 'self' 'wasm-unsafe-eval';\
+    style-src 'self';\
+    img-src 'self' data:;\
+    font-src 'self';\
+    connect-src 'self';\
+    worker-src 'self';\
+    frame-src 'none';\
+    object-src 'none';\
+    base-uri 'none';\
+    frame-ancestors 'none';\
+    form-action 'self';\
+    upgrade-insecure-requests\
+";
+// any https resource and inline or eval'd code, only blocking plugins and foreign framing
+const CSP_RELAXED: &[u8] = b"\
+    default-src 'self' https: data: blob: 'unsafe-inline' 'unsafe-eval';\
+    object-src 'none';\
+    base-uri 'self';\
+    frame-ancestors 'self'\
+";
+
+const PRESETS: &[(&str, Option<&[u8]>)] = &[
+    ("default", Some(CSP_DEFAULT)),
+    ("strict", Some(CSP_STRICT)),
+    ("relaxed", Some(CSP_RELAXED)),
+    ("none", None),
+];
+
+/// Cross-origin isolation of the static content, `credentialless` and `require-corp`
+/// both enable `SharedArrayBuffer` and WASM threads with the `same-origin` opener policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Isolation {
+    // no embedder policy
+    None,
+    // cross-origin resources need a CORP or CORS header
+    RequireCorp,
+    // cross-origin resources are loaded without credentials
+    #[default]
+    Credentialless,
+}
+
+/// A content security policy, from a preset name (`default`, `strict`, `relaxed` or `none`)
+/// or a template where `{<preset>}` expands to that preset (`{strict}; report-uri /csp`).
+#[derive(Clone, Debug)]
+pub struct CspPolicy(Option<String>);
+
+impl CspPolicy {
+    pub fn policy(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+}
+
+impl FromStr for CspPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let preset = |name: &str| {
+            PRESETS
+                .iter()
+                .find(|(it, _)| it.eq_ignore_ascii_case(name))
+                .map(|(_, policy)| policy.map(|it| String::from_utf8_lossy(it).into_owned()))
+        };
+        let s = s.trim();
+        if let Some(policy) = preset(s) {
+            return Ok(Self(policy));
+        }
+        let mut policy = String::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .map(|it| start + it)
+                .ok_or_else(|| format!("unclosed {{ in {s}"))?;
+            let name = &rest[start + 1..end];
+            policy.push_str(&rest[..start]);
+            match preset(name) {
+                Some(Some(it)) => policy.push_str(&it),
+                Some(None) => {}
+                None => {
+                    return Err(format!(
+                        "unknown preset {{{name}}}, expected one of {}",
+                        names()
+                    ));
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+        policy.push_str(rest);
+        let policy = policy.trim().trim_start_matches(';').trim();
+        if policy.is_empty() {
+            return Ok(Self(None));
+        }
+        if policy.bytes().any(|it| it.is_ascii_control()) {
+            return Err("the policy can't contain control characters".to_string());
+        }
+        // a lone word is a mistyped preset, unless it looks like a directive
+        // (`upgrade-insecure-requests`)
+        if !policy.contains([' ', ':', '\'', '-']) {
+            return Err(format!(
+                "unknown preset {policy}, expected one of {} or a policy",
+                names()
+            ));
+        }
+        Ok(Self(Some(policy.to_string())))
+    }
+}
+
+fn names() -> String {
+    PRESETS
+        .iter()
+        .map(|(it, _)| *it)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Security headers replacing the default embedder and content security policies.
+#[derive(Clone)]
+pub struct Security {
+    pub isolation: Isolation,
+    pub csp: CspPolicy,
+    /// Policy reported on but not enforced, to trial a stricter one
+    pub csp_report_only: Option<CspPolicy>,
+}
+
+impl Default for Security {
+    fn default() -> Self {
+        Self {
+            isolation: Isolation::default(),
+            csp: CspPolicy(Some(String::from_utf8_lossy(CSP_DEFAULT).into_owned())),
+            csp_report_only: None,
+        }
+    }
+}
+
+impl Security {
+    pub(crate) fn apply(&self, headers: &mut Vec<Line>) {
+        headers.retain(|it| it.key != COEP && it.key != CSP && it.key != CSP_REPORT_ONLY);
+        match self.isolation {
+            Isolation::None => {}
+            Isolation::RequireCorp => {
+                headers.push(Line::with_array_ref_value(COEP, b"require-corp"));
+            }
+            Isolation::Credentialless => {
+                headers.push(Line::with_array_ref_value(COEP, b"credentialless"));
+            }
+        }
+        if let Some(policy) = self.csp.policy() {
+            headers.push(Line::with_owned_value(CSP, policy.as_bytes().to_vec()));
+        }
+        if let Some(policy) = self.csp_report_only.as_ref().and_then(|it| it.policy()) {
+            headers.push(Line::with_owned_value(
+                CSP_REPORT_ONLY,
+                policy.as_bytes().to_vec(),
+            ));
+        }
+    }
+}
@@ -0,0 +1,726 @@
+use crate::adapter::{RequestAdapter, Sniffed};
+use crate::charset::Charsets;
+use crate::cors::Cors;
+use crate::dump::Dump;
+use crate::forward::{
+    ForwardError, ForwardPolicy, Proxy, StatusRule, Upstream, is_upgrade, proxy_for,
+};
+use crate::handler::{DEFAULT_DOT_PATHS, Handler};
+use crate::har::{CaptureBody, Recorder};
+use crate::log::{AccessLog, Entry, LogFormat};
+use crate::security::Security;
+use crate::types::DefaultHeaderSelector;
+use colored::Colorize;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{
+    CONNECTION, CONTENT_ENCODING, CONTENT_TYPE, HOST, HeaderValue, IF_NONE_MATCH, LOCATION,
+    REFERER, USER_AGENT,
+};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use rcgen::generate_simple_self_signed;
+use ring::digest::{SHA256, digest};
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::spawn;
+use tokio::sync::watch;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+
+pub struct Server;
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            address: SocketAddr::from((Ipv4Addr::LOCALHOST, 443)),
+            root: PathBuf::from("."),
+            prefix: String::new(),
+            cors: None,
+            forwarded: None,
+            forward_policy: ForwardPolicy::default(),
+            forward_on: vec![],
+            fallback_on: vec![],
+            static_on_upstream_error: false,
+            sniff: false,
+            dot_paths: vec![],
+            charsets: None,
+            security: None,
+            proxies: vec![],
+            access_log: None,
+            dump: None,
+            har: None,
+        }
+    }
+}
+
+pub struct ServerBuilder {
+    address: SocketAddr,
+    root: PathBuf,
+    prefix: String,
+    cors: Option<Cors>,
+    forwarded: Option<Upstream>,
+    forward_policy: ForwardPolicy,
+    forward_on: Vec<StatusRule>,
+    fallback_on: Vec<StatusRule>,
+    static_on_upstream_error: bool,
+    sniff: bool,
+    dot_paths: Vec<String>,
+    charsets: Option<Charsets>,
+    security: Option<Security>,
+    proxies: Vec<Proxy>,
+    access_log: Option<AccessLog>,
+    dump: Option<Dump>,
+    har: Option<&'static Recorder>,
+}
+
+impl ServerBuilder {
+    /// Address to listen on (127.0.0.1:443 by default, port 0 picks a free one)
+    pub fn address(mut self, address: SocketAddr) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// Directory with the static content (the current directory by default)
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = root.into();
+        self
+    }
+
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        let prefix = prefix.strip_prefix('/').unwrap_or(prefix);
+        self.prefix = prefix.strip_suffix('/').unwrap_or(prefix).to_string();
+        self
+    }
+
+    pub fn cors(mut self, cors: Cors) -> Self {
+        self.cors = Some(cors);
+        self
+    }
+
+    pub fn forwarded(mut self, upstream: Upstream) -> Self {
+        self.forwarded = Some(upstream);
+        self
+    }
+
+    pub fn forward_policy(mut self, policy: ForwardPolicy) -> Self {
+        self.forward_policy = policy;
+        self
+    }
+
+    /// Static response status that is forwarded with static-first (defaults to 4xx)
+    pub fn forward_on(mut self, rules: Vec<StatusRule>) -> Self {
+        self.forward_on = rules;
+        self
+    }
+
+    /// Upstream response status that falls back to static content with proxy-first (defaults to 404)
+    pub fn fallback_on(mut self, rules: Vec<StatusRule>) -> Self {
+        self.fallback_on = rules;
+        self
+    }
+
+    pub fn static_on_upstream_error(mut self, enabled: bool) -> Self {
+        self.static_on_upstream_error = enabled;
+        self
+    }
+
+    /// Type files without a known extension from their first bytes
+    pub fn sniff(mut self, enabled: bool) -> Self {
+        self.sniff = enabled;
+        self
+    }
+
+    /// Dotfiles and dot directories served despite the dotfile rule (defaults to `.well-known`)
+    pub fn dot_paths(mut self, paths: Vec<String>) -> Self {
+        self.dot_paths = paths
+            .into_iter()
+            .map(|it| it.trim_matches('/').to_string())
+            .collect();
+        self
+    }
+
+    /// Charset parameters of text types (`utf-8` by default)
+    pub fn charsets(mut self, charsets: Charsets) -> Self {
+        self.charsets = Some(charsets);
+        self
+    }
+
+    /// Embedder and content security policies of static content (credentialless and the
+    /// default CSP otherwise)
+    pub fn security(mut self, security: Security) -> Self {
+        self.security = Some(security);
+        self
+    }
+
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// Access log (pretty printed on stdout by default)
+    pub fn access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    pub fn dump(mut self, dump: Dump) -> Self {
+        self.dump = Some(dump);
+        self
+    }
+
+    pub fn har(mut self, recorder: &'static Recorder) -> Self {
+        self.har = Some(recorder);
+        self
+    }
+
+    /// Binds the address and starts accepting connections in the background.
+    /// The handler configuration is leaked, as it lives as long as the connections using it,
+    /// the rest is dropped with the handle and the last connection.
+    pub async fn start(self) -> std::io::Result<ServerHandle> {
+        let domains: Vec<String> = vec![
+            format!("{}", Ipv4Addr::LOCALHOST),
+            format!("{}", Ipv6Addr::LOCALHOST),
+        ];
+        let cert = generate_simple_self_signed(domains).map_err(std::io::Error::other)?;
+        let mut server_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.cert.der().clone()],
+                PrivateKeyDer::Pkcs8(cert.signing_key.serialize_der().into()),
+            )
+            .map_err(std::io::Error::other)?;
+        server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let tls_acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let listener = TcpListener::bind(self.address).await?;
+        // the actual port when 0 asked for an ephemeral one
+        let address = listener.local_addr()?;
+        let host = match address.ip() {
+            ip if ip.is_loopback() || ip.is_unspecified() => "localhost".to_string(),
+            IpAddr::V4(ip) => ip.to_string(),
+            IpAddr::V6(ip) => format!("[{ip}]"),
+        };
+        let url = format!(
+            "https://{host}{}/{}",
+            if address.port() != 443 {
+                format!(":{}", address.port())
+            } else {
+                "".to_string()
+            },
+            self.prefix
+        );
+        let context = Arc::new(Context {
+            root: Box::leak(self.root.into_boxed_path()),
+            prefix: self.prefix.leak(),
+            cors: self.cors.map(|it| &*Box::leak(Box::new(it))),
+            forwarded: self.forwarded,
+            forward_policy: self.forward_policy,
+            forward_on: if self.forward_on.is_empty() {
+                vec![StatusRule::Class(4)]
+            } else {
+                self.forward_on
+            },
+            fallback_on: if self.fallback_on.is_empty() {
+                vec![StatusRule::Exact(404)]
+            } else {
+                self.fallback_on
+            },
+            static_on_upstream_error: self.static_on_upstream_error,
+            sniff: self.sniff,
+            dot_paths: if self.dot_paths.is_empty() {
+                DEFAULT_DOT_PATHS
+            } else {
+                self.dot_paths
+                    .into_iter()
+                    .map(|it| &*it.leak())
+                    .collect::<Vec<_>>()
+                    .leak()
+            },
+            charsets: self.charsets.map(|it| &*Box::leak(Box::new(it))),
+            security: self.security.map(|it| &*Box::leak(Box::new(it))),
+            proxies: self.proxies,
+            access_log: match self.access_log {
+                Some(access_log) => access_log,
+                None => AccessLog::new(LogFormat::default(), None)?,
+            },
+            dump: self.dump,
+            har: self.har,
+        });
+        // every connection watches it, to finish its in-flight request and close
+        let (shutdown, receiver) = watch::channel(false);
+        let accept = spawn(accept(listener, tls_acceptor, receiver, context.clone()));
+        Ok(ServerHandle {
+            address,
+            url,
+            certificate: cert.cert.der().to_vec(),
+            started: Instant::now(),
+            context,
+            shutdown,
+            accept: Some(accept),
+        })
+    }
+}
+
+pub struct ServerHandle {
+    address: SocketAddr,
+    url: String,
+    certificate: Vec<u8>,
+    started: Instant,
+    context: Arc<Context>,
+    shutdown: watch::Sender<bool>,
+    // returns the connections still open once it stops accepting
+    accept: Option<JoinHandle<JoinSet<()>>>,
+}
+
+impl ServerHandle {
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// DER encoded self-signed certificate
+    pub fn certificate(&self) -> &[u8] {
+        &self.certificate
+    }
+
+    /// SHA-256 of the certificate, as colon separated hex
+    pub fn fingerprint(&self) -> String {
+        digest(&SHA256, &self.certificate)
+            .as_ref()
+            .iter()
+            .map(|it| format!("{it:02X}"))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+
+    pub fn summary(&self) -> String {
+        self.context.access_log.summary(self.started.elapsed())
+    }
+
+    /// Stops accepting connections and waits for the open ones to finish their in-flight
+    /// request, closing whatever is left after the timeout.
+    pub async fn shutdown(&mut self, timeout: Duration) {
+        let _ = self.shutdown.send(true);
+        let Some(accept) = self.accept.take() else {
+            return;
+        };
+        let Ok(mut connections) = accept.await else {
+            return;
+        };
+        while connections.try_join_next().is_some() {}
+        if connections.is_empty() {
+            return;
+        }
+        println!(
+            "{}",
+            format!("waiting for {} connection(s) to finish", connections.len()).dimmed()
+        );
+        let finished = async { while connections.join_next().await.is_some() {} };
+        if tokio::time::timeout(timeout, finished).await.is_err() {
+            println!(
+                "{}",
+                format!("closing {} connection(s)", connections.len()).red()
+            );
+            connections.abort_all();
+            while connections.join_next().await.is_some() {}
+        }
+    }
+}
+
+impl Context {
+    fn handler(&self) -> Handler<DefaultHeaderSelector> {
+        Handler {
+            root: self.root,
+            prefix: self.prefix,
+            header_selector: DefaultHeaderSelector,
+            cors: self.cors,
+            sniff: self.sniff,
+            dot_paths: self.dot_paths,
+            charsets: self.charsets,
+            security: self.security,
+        }
+    }
+}
+
+struct Context {
+    root: &'static Path,
+    prefix: &'static str,
+    cors: Option<&'static Cors>,
+    forwarded: Option<Upstream>,
+    forward_policy: ForwardPolicy,
+    forward_on: Vec<StatusRule>,
+    fallback_on: Vec<StatusRule>,
+    static_on_upstream_error: bool,
+    sniff: bool,
+    dot_paths: &'static [&'static str],
+    charsets: Option<&'static Charsets>,
+    security: Option<&'static Security>,
+    proxies: Vec<Proxy>,
+    access_log: AccessLog,
+    dump: Option<Dump>,
+    har: Option<&'static Recorder>,
+}
+
+async fn accept(
+    listener: TcpListener,
+    tls_acceptor: TlsAcceptor,
+    mut shutdown: watch::Receiver<bool>,
+    context: Arc<Context>,
+) -> JoinSet<()> {
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((tcp_stream, remote_address)) => {
+                    connections.spawn(serve_connection(
+                        tcp_stream,
+                        remote_address,
+                        tls_acceptor.clone(),
+                        shutdown.clone(),
+                        context.clone(),
+                    ));
+                }
+                Err(err) => {
+                    println!("{} {err}", "failed to accept connection:".red());
+                }
+            },
+            // forgets the closed ones
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = shutdown.changed() => break,
+        }
+    }
+    connections
+}
+
+async fn serve_connection(
+    tcp_stream: TcpStream,
+    remote_address: SocketAddr,
+    tls_acceptor: TlsAcceptor,
+    mut shutdown: watch::Receiver<bool>,
+    context: Arc<Context>,
+) {
+    let mut first_byte = [0u8; 1];
+    // an idle preconnect socket would otherwise hold the shutdown until its timeout
+    let peeked = tokio::select! {
+        peeked = tcp_stream.peek(&mut first_byte) => peeked,
+        _ = shutdown.changed() => return,
+    };
+    match peeked {
+        // closed without sending anything (e.g. a speculative preconnect)
+        Ok(0) => return,
+        Ok(_) => {}
+        Err(err) => {
+            println!("{} {remote_address}: {err}", "connection error from".red());
+            return;
+        }
+    }
+    // a TLS connection always starts with a handshake record (0x16)
+    if first_byte[0].is_ascii_uppercase() {
+        println!(
+            "{} {remote_address}, redirecting to https",
+            "plain HTTP request on the TLS port from".red()
+        );
+        let _ = http1::Builder::new()
+            .serve_connection(TokioIo::new(tcp_stream), service_fn(redirect_to_https))
+            .await;
+        return;
+    }
+    let accepted = tokio::select! {
+        accepted = tls_acceptor.accept(tcp_stream) => accepted,
+        _ = shutdown.changed() => return,
+    };
+    let tls_stream = match accepted {
+        Ok(tls_stream) => tls_stream,
+        Err(err) => {
+            println!(
+                "{} {remote_address}: {err}",
+                "TLS handshake failed with".red()
+            );
+            return;
+        }
+    };
+    let io = TokioIo::new(tls_stream);
+    let connection = http1::Builder::new()
+        .serve_connection(
+            io,
+            service_fn(move |request: Request<Incoming>| {
+                handle(request, remote_address, context.clone())
+            }),
+        )
+        .with_upgrades();
+    tokio::pin!(connection);
+    let result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = shutdown.changed() => {
+            // finishes the in-flight request, then closes
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+    if let Err(err) = result {
+        println!("{} {remote_address}: {err}", "connection error from".red());
+    }
+}
+
+async fn redirect_to_https(
+    request: Request<Incoming>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, Infallible> {
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|it| it.to_str().ok())
+        .unwrap_or("localhost");
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|it| it.as_str())
+        .unwrap_or("/");
+    let location = format!("https://{host}{path}");
+    Ok(Response::builder()
+        .status(StatusCode::TEMPORARY_REDIRECT)
+        .header(LOCATION, &location)
+        .header(CONNECTION, "close")
+        .header(CONTENT_TYPE, "text/plain")
+        .body(
+            Full::new(Bytes::from(format!(
+                "This server only speaks HTTPS, use {location}\n"
+            )))
+            .map_err(|err: Infallible| match err {})
+            .boxed(),
+        )
+        .unwrap())
+}
+
+async fn handle(
+    request: Request<Incoming>,
+    remote_address: SocketAddr,
+    context: Arc<Context>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, Infallible> {
+    let time = SystemTime::now();
+    let started = Instant::now();
+    let header = |name| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|it: &HeaderValue| it.to_str().ok())
+            .map(|it| it.to_string())
+    };
+    let referer = header(REFERER);
+    let user_agent = header(USER_AGENT);
+    let conditional = request.headers().contains_key(IF_NONE_MATCH);
+    let method = request.method().to_string();
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|it| it.as_str())
+        .unwrap_or("/")
+        .to_string();
+    let uri_path = request.uri().path().to_string();
+    let version = format!("{:?}", request.version());
+    let request_headers = context.dump.as_ref().map(|_| request.headers().clone());
+    let request = request.map(|it| it.boxed());
+    let (request, har) = match context.har {
+        Some(har) => {
+            let (request, pending) = har.record_request(request);
+            (request, Some((har, pending)))
+        }
+        None => (request, None),
+    };
+    let mut upstream_duration = None;
+    let (response, forwarded) =
+        respond(request, remote_address, &context, &mut upstream_duration).await;
+    let response = match har {
+        Some((har, pending)) => har.record_response(pending, response, forwarded),
+        None => response,
+    };
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND
+        && !forwarded
+        && let Some(file) = context.handler().unmapped_file(uri_path.as_bytes()).await
+    {
+        let reason = if context.sniff {
+            "its extension has no known type and its content was not recognised"
+        } else {
+            "its extension has no known type"
+        };
+        println!(
+            "{}",
+            format!("{file} exists but is not served, {reason}").yellow()
+        );
+    } else if !forwarded && let Some(Sniffed(file)) = response.extensions().get() {
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|it| it.to_str().ok())
+            .unwrap_or("no content type");
+        println!(
+            "{}",
+            format!("{file} has no known extension, sniffed as {content_type}").dimmed()
+        );
+    }
+    let dump = context
+        .dump
+        .as_ref()
+        .filter(|it| it.matches(&path, status))
+        .zip(request_headers);
+    if let Some((dump, request_headers)) = dump {
+        dump.print(&request_headers, response.headers());
+    }
+    let entry = Entry {
+        time,
+        remote_address,
+        method,
+        path,
+        version,
+        status: status.as_u16(),
+        bytes: 0,
+        duration: Duration::ZERO,
+        content_encoding: response
+            .headers()
+            .get(CONTENT_ENCODING)
+            .and_then(|it| it.to_str().ok())
+            .map(|it| it.to_string()),
+        cache: if !conditional {
+            None
+        } else if status == StatusCode::NOT_MODIFIED {
+            Some("hit")
+        } else {
+            Some("miss")
+        },
+        forwarded,
+        upstream_duration,
+        referer,
+        user_agent,
+    };
+    // logged once the body is sent, with its actual size
+    let (parts, body) = response.into_parts();
+    let body = CaptureBody::new(body, false, move |captured| {
+        let mut entry = entry;
+        entry.bytes = captured.size;
+        entry.duration = started.elapsed();
+        context.access_log.log(&entry);
+    });
+    Ok(Response::from_parts(parts, body.boxed()))
+}
+
+async fn timed<T>(future: impl Future<Output = T>, duration: &mut Option<Duration>) -> T {
+    let started = Instant::now();
+    let result = future.await;
+    *duration = Some(started.elapsed());
+    result
+}
+
+async fn respond(
+    request: Request<BoxBody<Bytes, hyper::Error>>,
+    remote_address: SocketAddr,
+    context: &Context,
+    upstream_duration: &mut Option<Duration>,
+) -> (Response<BoxBody<Bytes, hyper::Error>>, bool) {
+    if let Some(proxy) = proxy_for(&context.proxies, request.uri().path()) {
+        let response = timed(proxy.forward(request, remote_address), upstream_duration)
+            .await
+            .unwrap_or_else(bad_gateway);
+        return (response, true);
+    }
+    // websockets (the vite HMR one connects to /?token=...) would get the static index
+    if let Some(upstream) = context.forwarded.as_ref()
+        && is_upgrade(&request)
+    {
+        let response = timed(
+            upstream.forward(request, remote_address, None),
+            upstream_duration,
+        )
+        .await
+        .unwrap_or_else(bad_gateway);
+        return (response, true);
+    }
+    let handler = context.handler();
+    let (parts, body) = request.into_parts();
+    let static_response = |parts| {
+        handler.handle(RequestAdapter {
+            inner: Request::from_parts(parts, empty_body()),
+            sniffed: None,
+        })
+    };
+    match context.forwarded.as_ref() {
+        Some(upstream) if matches!(context.forward_policy, ForwardPolicy::ProxyFirst) => {
+            let request = Request::from_parts(parts.clone(), body);
+            match timed(
+                upstream.forward(request, remote_address, None),
+                upstream_duration,
+            )
+            .await
+            {
+                Ok(forward_response)
+                    if !StatusRule::matches_any(
+                        &context.fallback_on,
+                        forward_response.status(),
+                    ) =>
+                {
+                    (forward_response, true)
+                }
+                Ok(_) => (static_response(parts).await, false),
+                Err(err) => {
+                    println!("{}\n{err:?}", "error on forwarded response".red());
+                    (static_response(parts).await, false)
+                }
+            }
+        }
+        _ => {
+            let response = static_response(parts.clone()).await;
+            if !StatusRule::matches_any(&context.forward_on, response.status()) {
+                (response, false)
+            } else if let Some(upstream) = context.forwarded.as_ref() {
+                let request = Request::from_parts(parts, body);
+                match timed(
+                    upstream.forward(request, remote_address, None),
+                    upstream_duration,
+                )
+                .await
+                {
+                    Ok(forward_response)
+                        if context.static_on_upstream_error
+                            && (forward_response.status().is_client_error()
+                                || forward_response.status().is_server_error()) =>
+                    {
+                        (response, false)
+                    }
+                    Ok(forward_response) => (forward_response, true),
+                    Err(err) => {
+                        println!("{}\n{err:?}", "error on forwarded response".red());
+                        (response, false)
+                    }
+                }
+            } else {
+                let _ = body.collect().await;
+                (response, false)
+            }
+        }
+    }
+}
+
+fn bad_gateway(err: ForwardError) -> Response<BoxBody<Bytes, hyper::Error>> {
+    println!("{}\n{err:?}", "error on forwarded response".red());
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .body(empty_body())
+        .unwrap()
+}
+
+fn empty_body() -> BoxBody<Bytes, hyper::Error> {
+    Empty::<Bytes>::new()
+        .map_err(|err: Infallible| match err {})
+        .boxed()
+}
@@ -1,8 +1,10 @@
 use crate::handler::{HeaderSelector, HeadersAndCompression};
 use crate::http::headers::{
-    Line, ALLOW, CACHE_CONTROL, COEP, CONTENT_LENGTH, CONTENT_TYPE, COOP, CORP, CSP, HSTS,
+    ALLOW, CACHE_CONTROL, COEP, CONTENT_LENGTH, CONTENT_TYPE, COOP, CORP, CSP, HSTS, Line,
     SERVICE_WORKER_ALLOWED, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS, X_XSS_PROTECTION,
 };
+use crate::path::{extension, filename};
+use crate::security::CSP_DEFAULT;
 use std::sync::LazyLock;
 
 pub static DEFAULT_HEADERS: LazyLock<Vec<Line>> = LazyLock::new(|| {
@@ -12,25 +14,9 @@ pub static DEFAULT_HEADERS: LazyLock<Vec<Line>> = LazyLock::new(|| {
         (X_FRAME_OPTIONS, b"DENY".as_slice()).into(),
         (X_XSS_PROTECTION, b"1; mode=block".as_slice()).into(),
         (CORP, b"same-site".as_slice()).into(),
-        (COEP, b"crendentialless".as_slice()).into(),
+        (COEP, b"credentialless".as_slice()).into(),
         (COOP, b"same-origin".as_slice()).into(),
-        (CSP,
-            b"\
-                default-src 'self';\
-                connect-src 'self' https: data: blob:;\
-                script-src 'wasm-unsafe-eval';\
-                script-src-elem 'self' 'unsafe-inline';\
-                script-src-attr 'none';\
-                worker-src 'self' blob:;\
-                style-src 'self' 'unsafe-inline';\
-                img-src 'self' data: blob:;\
-                font-src 'self' data:;\
-                frame-src 'none';\
-                object-src 'none';\
-                base-uri 'none';\
-                frame-ancestors 'none';\
-                form-action 'none'\
-            ".as_slice()).into(),
+        (CSP, CSP_DEFAULT).into(),
         (HSTS, b"max-age=63072000; includeSubDomains; preload".as_slice()).into(),
     ];
     headers
@@ -60,6 +46,57 @@ pub(crate) fn default_error_headers() -> &'static [Line] {
     ERROR_HEADERS.as_slice()
 }
 
+// files with a type set by their specification rather than by their extension
+fn well_known_headers(name: &str) -> Option<HeadersAndCompression> {
+    if name.starts_with("acme-challenge/") {
+        return Some(headers_and_compression(
+            Some(b"text/plain"),
+            Some(CACHE_CONTROL_NO_CACHE),
+            false,
+        ));
+    }
+    match name {
+        "apple-app-site-association"
+        | "openid-configuration"
+        | "oauth-authorization-server"
+        | "nodeinfo" => Some(headers_and_compression(
+            Some(b"application/json"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
+        "webfinger" | "host-meta.json" => Some(headers_and_compression(
+            Some(b"application/jrd+json"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
+        "host-meta" => Some(headers_and_compression(
+            Some(b"application/xrd+xml"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
+        "traffic-advice" => Some(headers_and_compression(
+            Some(b"application/trafficadvice+json"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
+        _ => None,
+    }
+}
+
+/// The headers for a path relative to the root, `.well-known` files having their own types.
+pub(crate) fn headers_for_path(path: &str) -> Option<HeadersAndCompression> {
+    if let Some(name) = path.strip_prefix(".well-known/")
+        && let Some(headers) = well_known_headers(name)
+    {
+        return Some(headers);
+    }
+    let filename = filename(path);
+    headers_for_type(filename, extension(filename))
+}
+
+// Compressible types are the ones storing text or raw data (markup, scripts, json/xml based
+// formats, uncompressed fonts, bitmaps and audio). Formats that already compress their content
+// (woff/woff2, png, jpeg and other modern images, audio/video codecs, archives, pdf) are not.
 pub(crate) fn headers_for_type(filename: &str, extension: &str) -> Option<HeadersAndCompression> {
     match extension {
         "html" | "htm" => Some(headers_and_compression(
@@ -67,12 +104,17 @@ pub(crate) fn headers_for_type(filename: &str, extension: &str) -> Option<Header
             Some(CACHE_CONTROL_REVALIDATE),
             true,
         )),
+        "xhtml" => Some(headers_and_compression(
+            Some(b"application/xhtml+xml"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
         "css" => Some(headers_and_compression(
             Some(b"text/css"),
             Some(CACHE_CONTROL_REVALIDATE),
             true,
         )),
-        "js" | "mjs" | "map" => Some(
+        "js" | "mjs" | "cjs" => Some(
             if filename.starts_with("service-worker.") || filename.starts_with("sw.") {
                 let mut headers_and_compression = headers_and_compression(
                     Some(b"application/javascript"),
@@ -91,59 +133,85 @@ pub(crate) fn headers_for_type(filename: &str, extension: &str) -> Option<Header
                 )
             },
         ),
-        "json" => Some(
-            if filename.starts_with("manifest.") || filename.ends_with(".manifest") {
-                headers_and_compression(
-                    Some(b"application/manifest+json"),
-                    Some(CACHE_CONTROL_DEFAULT),
-                    true,
-                )
-            } else if filename.ends_with(".ld") {
-                headers_and_compression(
-                    Some(b"application/ld+json"),
-                    Some(CACHE_CONTROL_DEFAULT),
-                    true,
-                )
-            } else if filename.ends_with(".schema") {
-                headers_and_compression(
-                    Some(b"application/schema+json"),
-                    Some(CACHE_CONTROL_DEFAULT),
-                    true,
-                )
-            } else {
-                headers_and_compression(
-                    Some(b"application/json"),
-                    Some(CACHE_CONTROL_REVALIDATE),
-                    true,
-                )
-            },
-        ),
-        "xml" => Some(
-            if filename.starts_with("atom.") || filename.ends_with(".atom") {
-                headers_and_compression(
-                    Some(b"application/atom+xml"),
-                    Some(CACHE_CONTROL_DEFAULT),
-                    true,
-                )
-            } else if filename.ends_with(".dtd") {
-                headers_and_compression(
-                    Some(b"application/xnl-dtd"),
-                    Some(CACHE_CONTROL_DEFAULT),
-                    true,
-                )
-            } else {
-                headers_and_compression(
-                    Some(b"application/xml"),
-                    Some(CACHE_CONTROL_REVALIDATE),
-                    true,
-                )
-            },
-        ),
-        "ldjson" => Some(headers_and_compression(
+        "map" => Some(headers_and_compression(
+            Some(b"application/json"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
+        "json" => Some(if filename.starts_with("manifest.") {
+            headers_and_compression(
+                Some(b"application/manifest+json"),
+                Some(CACHE_CONTROL_DEFAULT),
+                true,
+            )
+        } else if filename.ends_with(".ld.json") {
+            headers_and_compression(
+                Some(b"application/ld+json"),
+                Some(CACHE_CONTROL_DEFAULT),
+                true,
+            )
+        } else if filename.ends_with(".schema.json") {
+            headers_and_compression(
+                Some(b"application/schema+json"),
+                Some(CACHE_CONTROL_DEFAULT),
+                true,
+            )
+        } else {
+            headers_and_compression(
+                Some(b"application/json"),
+                Some(CACHE_CONTROL_REVALIDATE),
+                true,
+            )
+        }),
+        "webmanifest" => Some(headers_and_compression(
+            Some(b"application/manifest+json"),
+            Some(CACHE_CONTROL_DEFAULT),
+            true,
+        )),
+        "jsonld" | "ldjson" => Some(headers_and_compression(
             Some(b"application/ld+json"),
             Some(CACHE_CONTROL_DEFAULT),
             true,
         )),
+        "xml" => Some(if filename.starts_with("atom.") {
+            headers_and_compression(
+                Some(b"application/atom+xml"),
+                Some(CACHE_CONTROL_DEFAULT),
+                true,
+            )
+        } else if filename.starts_with("rss.") {
+            headers_and_compression(
+                Some(b"application/rss+xml"),
+                Some(CACHE_CONTROL_DEFAULT),
+                true,
+            )
+        } else {
+            headers_and_compression(
+                Some(b"application/xml"),
+                Some(CACHE_CONTROL_REVALIDATE),
+                true,
+            )
+        }),
+        "atom" => Some(headers_and_compression(
+            Some(b"application/atom+xml"),
+            Some(CACHE_CONTROL_DEFAULT),
+            true,
+        )),
+        "rss" => Some(headers_and_compression(
+            Some(b"application/rss+xml"),
+            Some(CACHE_CONTROL_DEFAULT),
+            true,
+        )),
+        "dtd" => Some(headers_and_compression(
+            Some(b"application/xml-dtd"),
+            Some(CACHE_CONTROL_DEFAULT),
+            true,
+        )),
+        "xsl" | "xslt" => Some(headers_and_compression(
+            Some(b"application/xslt+xml"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
         "txt" => Some(headers_and_compression(
             Some(b"text/plain"),
             Some(CACHE_CONTROL_REVALIDATE),
@@ -159,6 +227,26 @@ pub(crate) fn headers_for_type(filename: &str, extension: &str) -> Option<Header
             Some(CACHE_CONTROL_REVALIDATE),
             true,
         )),
+        "vtt" => Some(headers_and_compression(
+            Some(b"text/vtt"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
+        "ics" => Some(headers_and_compression(
+            Some(b"text/calendar"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
+        "yaml" | "yml" => Some(headers_and_compression(
+            Some(b"application/yaml"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
+        "toml" => Some(headers_and_compression(
+            Some(b"application/toml"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
         "wasm" => Some(headers_and_compression(
             Some(b"application/wasm"),
             Some(CACHE_CONTROL_REVALIDATE),
@@ -169,11 +257,46 @@ pub(crate) fn headers_for_type(filename: &str, extension: &str) -> Option<Header
             Some(CACHE_CONTROL_REVALIDATE),
             false,
         )),
+        "woff" => Some(headers_and_compression(
+            Some(b"font/woff"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            false,
+        )),
+        "ttf" => Some(headers_and_compression(
+            Some(b"font/ttf"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
+        "otf" => Some(headers_and_compression(
+            Some(b"font/otf"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
+        "eot" => Some(headers_and_compression(
+            Some(b"application/vnd.ms-fontobject"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
         "ico" => Some(headers_and_compression(
             Some(b"image/x-icon"),
             Some(CACHE_CONTROL_IMMUTABLE),
             true,
         )),
+        "bmp" => Some(headers_and_compression(
+            Some(b"image/bmp"),
+            Some(CACHE_CONTROL_IMMUTABLE),
+            true,
+        )),
+        "tif" | "tiff" => Some(headers_and_compression(
+            Some(b"image/tiff"),
+            Some(CACHE_CONTROL_IMMUTABLE),
+            true,
+        )),
+        "svg" => Some(headers_and_compression(
+            Some(b"image/svg+xml"),
+            Some(CACHE_CONTROL_IMMUTABLE),
+            true,
+        )),
         "webp" => Some(headers_and_compression(
             Some(b"image/webp"),
             Some(CACHE_CONTROL_IMMUTABLE),
@@ -199,17 +322,22 @@ pub(crate) fn headers_for_type(filename: &str, extension: &str) -> Option<Header
             Some(CACHE_CONTROL_IMMUTABLE),
             false,
         )),
+        "jxl" => Some(headers_and_compression(
+            Some(b"image/jxl"),
+            Some(CACHE_CONTROL_IMMUTABLE),
+            false,
+        )),
         "png" => Some(headers_and_compression(
             Some(b"image/png"),
             Some(CACHE_CONTROL_IMMUTABLE),
             false,
         )),
-        "jpg" => Some(headers_and_compression(
-            Some(b"image/jpeg"),
+        "apng" => Some(headers_and_compression(
+            Some(b"image/apng"),
             Some(CACHE_CONTROL_IMMUTABLE),
             false,
         )),
-        "jpeg" => Some(headers_and_compression(
+        "jpg" | "jpeg" | "jpe" | "jfif" | "pjpeg" | "pjp" => Some(headers_and_compression(
             Some(b"image/jpeg"),
             Some(CACHE_CONTROL_IMMUTABLE),
             false,
@@ -220,7 +348,12 @@ pub(crate) fn headers_for_type(filename: &str, extension: &str) -> Option<Header
             false,
         )),
         "mp3" => Some(headers_and_compression(
-            Some(b"audio/mp3"),
+            Some(b"audio/mpeg"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            false,
+        )),
+        "m4a" => Some(headers_and_compression(
+            Some(b"audio/mp4"),
             Some(CACHE_CONTROL_REVALIDATE),
             false,
         )),
@@ -229,25 +362,60 @@ pub(crate) fn headers_for_type(filename: &str, extension: &str) -> Option<Header
             Some(CACHE_CONTROL_REVALIDATE),
             false,
         )),
-        "webm" => Some(headers_and_compression(
+        "ogg" | "oga" | "opus" => Some(headers_and_compression(
+            Some(b"audio/ogg"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            false,
+        )),
+        "weba" => Some(headers_and_compression(
             Some(b"audio/webm"),
             Some(CACHE_CONTROL_REVALIDATE),
             false,
         )),
-        "mp4" => Some(headers_and_compression(
+        "wav" => Some(headers_and_compression(
+            Some(b"audio/wav"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
+        "mid" | "midi" => Some(headers_and_compression(
+            Some(b"audio/midi"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            true,
+        )),
+        "webm" => Some(headers_and_compression(
+            Some(b"video/webm"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            false,
+        )),
+        "mp4" | "m4v" => Some(headers_and_compression(
             Some(b"video/mp4"),
             Some(CACHE_CONTROL_REVALIDATE),
             false,
         )),
-        "svg" => Some(headers_and_compression(
-            Some(b"image/svg+xml"),
-            Some(CACHE_CONTROL_IMMUTABLE),
+        "ogv" => Some(headers_and_compression(
+            Some(b"video/ogg"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            false,
+        )),
+        "mov" => Some(headers_and_compression(
+            Some(b"video/quicktime"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            false,
+        )),
+        "m3u8" => Some(headers_and_compression(
+            Some(b"application/vnd.apple.mpegurl"),
+            Some(CACHE_CONTROL_NO_CACHE),
             true,
         )),
+        "ts" => Some(headers_and_compression(
+            Some(b"video/mp2t"),
+            Some(CACHE_CONTROL_REVALIDATE),
+            false,
+        )),
         "pdf" => Some(headers_and_compression(
             Some(b"application/pdf"),
             Some(CACHE_CONTROL_REVALIDATE),
-            true,
+            false,
         )),
         "gpx" => Some(headers_and_compression(
             Some(b"application/gpx+xml"),
@@ -264,6 +432,11 @@ pub(crate) fn headers_for_type(filename: &str, extension: &str) -> Option<Header
             Some(CACHE_CONTROL_DEFAULT),
             true,
         )),
+        "gltf" => Some(headers_and_compression(
+            Some(b"model/gltf+json"),
+            Some(CACHE_CONTROL_DEFAULT),
+            true,
+        )),
         "glb" => Some(headers_and_compression(
             Some(b"model/gltf-binary"),
             Some(CACHE_CONTROL_DEFAULT),
@@ -289,6 +462,25 @@ pub(crate) fn headers_for_type(filename: &str, extension: &str) -> Option<Header
     }
 }
 
+pub(crate) const DEFAULT_CHARSET: &str = "utf-8";
+
+/// Whether a content type is text, and so gets a charset parameter.
+pub(crate) fn is_text(content_type: &[u8]) -> bool {
+    content_type.starts_with(b"text/")
+        || content_type.ends_with(b"+json")
+        || content_type.ends_with(b"+xml")
+        || matches!(
+            content_type,
+            b"application/javascript"
+                | b"application/json"
+                | b"application/xml"
+                | b"application/xml-dtd"
+                | b"application/yaml"
+                | b"application/toml"
+                | b"application/vnd.apple.mpegurl"
+        )
+}
+
 fn headers_and_compression(
     content_type: Option<&'static [u8]>,
     cache_control: Option<&'static [u8]>,
@@ -297,7 +489,15 @@ fn headers_and_compression(
     let default_headers = default_headers();
     let mut new_headers = vec![];
     if let Some(content_type) = content_type {
-        new_headers.push(Line::with_slice_value(CONTENT_TYPE, content_type));
+        // text is assumed to be UTF-8, `Charsets` can change that per type
+        if is_text(content_type) {
+            new_headers.push(Line::with_owned_value(
+                CONTENT_TYPE,
+                [content_type, b"; charset=", DEFAULT_CHARSET.as_bytes()].concat(),
+            ));
+        } else {
+            new_headers.push(Line::with_slice_value(CONTENT_TYPE, content_type));
+        }
     }
     if let Some(cache_control) = cache_control {
         new_headers.push(Line::with_slice_value(CACHE_CONTROL, cache_control));
@@ -313,7 +513,82 @@ fn headers_and_compression(
     }
 }
 
-pub(crate) struct DefaultHeaderSelector;
+/// The extension of the format recognised from the first bytes of a file, for files
+/// without a known extension.
+pub(crate) fn sniffed_extension(content: &[u8]) -> Option<&'static str> {
+    match content {
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => return Some("png"),
+        [0xff, 0xd8, 0xff, ..] => return Some("jpg"),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => return Some("gif"),
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'W',
+            b'E',
+            b'B',
+            b'P',
+            ..,
+        ] => return Some("webp"),
+        [
+            _,
+            _,
+            _,
+            _,
+            b'f',
+            b't',
+            b'y',
+            b'p',
+            b'a',
+            b'v',
+            b'i',
+            b'f' | b's',
+            ..,
+        ] => {
+            return Some("avif");
+        }
+        [0x00, b'a', b's', b'm', ..] => return Some("wasm"),
+        [b'%', b'P', b'D', b'F', b'-', ..] => return Some("pdf"),
+        [b'P', b'K', 0x03, 0x04, ..] | [b'P', b'K', 0x05, 0x06, ..] => return Some("zip"),
+        _ => {}
+    }
+    // text formats, which never contain a NUL byte
+    if content.contains(&0) {
+        return None;
+    }
+    let text = content.strip_prefix(b"\xef\xbb\xbf").unwrap_or(content);
+    let text = text.trim_ascii_start();
+    let starts_with = |prefix: &[u8]| {
+        text.len() >= prefix.len() && text[..prefix.len()].eq_ignore_ascii_case(prefix)
+    };
+    match text {
+        [b'{', rest @ ..] => match rest.trim_ascii_start() {
+            [b'"' | b'}', ..] => Some("json"),
+            _ => None,
+        },
+        [b'[', rest @ ..] => match rest.trim_ascii_start() {
+            [
+                b'"' | b'{' | b'[' | b']' | b'-' | b'0'..=b'9' | b't' | b'f' | b'n',
+                ..,
+            ] => Some("json"),
+            _ => None,
+        },
+        _ if ["<!doctype html", "<html", "<head", "<body", "<!--"]
+            .iter()
+            .any(|it| starts_with(it.as_bytes())) =>
+        {
+            Some("html")
+        }
+        _ => None,
+    }
+}
+
+pub struct DefaultHeaderSelector;
 
 impl HeaderSelector for DefaultHeaderSelector {
     fn headers_for_extension(
@@ -323,6 +598,12 @@ impl HeaderSelector for DefaultHeaderSelector {
     ) -> Option<HeadersAndCompression> {
         headers_for_type(filename, extension)
     }
+    fn headers_for_path(&self, path: &str) -> Option<HeadersAndCompression> {
+        headers_for_path(path)
+    }
+    fn headers_for_content(&self, content: &[u8]) -> Option<HeadersAndCompression> {
+        headers_for_type("", sniffed_extension(content)?)
+    }
     fn error_headers(&self) -> &'static [Line] {
         default_error_headers()
     }
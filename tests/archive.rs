@@ -0,0 +1,117 @@
+mod common;
+
+use common::site;
+use serve::archive::{build_zip, redirect_location};
+use serve::handler::DEFAULT_DOT_PATHS;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use tempfile::TempDir;
+use zip::ZipArchive;
+
+fn entry(zip: &Path, name: &str) -> Option<Vec<u8>> {
+    let mut archive = ZipArchive::new(File::open(zip).unwrap()).unwrap();
+    let mut file = archive.by_name(name).ok()?;
+    let mut content = vec![];
+    file.read_to_end(&mut content).unwrap();
+    Some(content)
+}
+
+#[test]
+fn packs_served_files_with_brotli_siblings() {
+    let html = "<p>hello</p>".repeat(100);
+    let root = site(&[
+        ("index.html", html.as_bytes()),
+        ("tiny.css", b"a{}"),
+        ("image.png", b"\x89PNG"),
+        ("old.307", b"/index\n"),
+        ("stale.html.br", b"old"),
+        (".env", b"SECRET=1"),
+        ("docs/.draft.html", b"draft"),
+        ("notes.unknownext", b"notes"),
+    ]);
+    let output = TempDir::new().unwrap();
+    let zip = output.path().join("site.zip");
+    let summary = build_zip(root.path(), &zip, 5, DEFAULT_DOT_PATHS).unwrap();
+    assert_eq!(summary.files, 4);
+    assert_eq!(summary.compressed, 1);
+    assert_eq!(entry(&zip, "index.html").unwrap(), html.as_bytes());
+    let mut decompressed = vec![];
+    brotli::Decompressor::new(entry(&zip, "index.html.br").unwrap().as_slice(), 4096)
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_eq!(decompressed, html.as_bytes());
+    // larger once compressed
+    assert!(entry(&zip, "tiny.css").is_some());
+    assert!(entry(&zip, "tiny.css.br").is_none());
+    assert!(entry(&zip, "image.png.br").is_none());
+    assert_eq!(entry(&zip, "old.307").unwrap(), b"/index\n");
+    for skipped in [
+        "stale.html.br",
+        ".env",
+        "docs/.draft.html",
+        "notes.unknownext",
+    ] {
+        assert!(entry(&zip, skipped).is_none(), "{skipped}");
+        assert!(summary.skipped.iter().any(|(path, _)| path == skipped));
+    }
+}
+
+#[test]
+fn rejects_invalid_redirects() {
+    let root = site(&[
+        ("index.html", b"<p>home</p>"),
+        ("empty.308", b""),
+        ("relative.307", b"somewhere"),
+    ]);
+    let zip = root.path().join("site.zip");
+    let err = build_zip(root.path(), &zip, 5, DEFAULT_DOT_PATHS)
+        .err()
+        .unwrap();
+    assert!(err.contains("empty.308"), "{err}");
+    assert!(err.contains("relative.307"), "{err}");
+    assert!(!zip.exists());
+}
+
+#[test]
+fn warns_about_shadowed_redirects() {
+    let root = site(&[("page.html", b"<p>page</p>"), ("page.307", b"/other")]);
+    let zip = root.path().join("site.zip");
+    let summary = build_zip(root.path(), &zip, 5, DEFAULT_DOT_PATHS).unwrap();
+    assert_eq!(summary.warnings.len(), 1);
+    assert!(summary.warnings[0].starts_with("page.307"));
+    // the output inside the root is not packed into itself
+    let summary = build_zip(root.path(), &zip, 5, DEFAULT_DOT_PATHS).unwrap();
+    assert_eq!(summary.files, 2);
+}
+
+#[test]
+fn redirect_locations() {
+    assert_eq!(redirect_location(b"/path?q=1\n"), Ok("/path?q=1"));
+    assert_eq!(
+        redirect_location(b"https://example.com/ comment"),
+        Ok("https://example.com/")
+    );
+    assert!(redirect_location(b"").is_err());
+    assert!(redirect_location(b"\n/path").is_err());
+    assert!(redirect_location(b"//example.com/").is_err());
+    assert!(redirect_location(b"ftp://example.com/").is_err());
+    assert!(redirect_location(b"relative/path").is_err());
+}
+
+#[test]
+fn packs_the_configured_dot_paths() {
+    let root = site(&[
+        ("index.html", b"<p>home</p>"),
+        (".well-known/security.txt", b"Contact: mailto:a@example.com"),
+        (".config/app.json", b"{}"),
+    ]);
+    let output = TempDir::new().unwrap();
+    let zip = output.path().join("site.zip");
+    build_zip(root.path(), &zip, 5, DEFAULT_DOT_PATHS).unwrap();
+    assert!(entry(&zip, ".well-known/security.txt").is_some());
+    assert!(entry(&zip, ".config/app.json").is_none());
+    build_zip(root.path(), &zip, 5, &[".config"]).unwrap();
+    assert!(entry(&zip, ".well-known/security.txt").is_none());
+    assert!(entry(&zip, ".config/app.json").is_some());
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use brotli::CompressorWriter;
+use serve::charset::{CharsetRule, Charsets};
+use serve::handler::{DEFAULT_DOT_PATHS, Handler};
+use serve::http::headers::Line;
+use serve::http::request::Request;
+use serve::http::response::StatusCode;
+use serve::types::DefaultHeaderSelector;
+use std::io::Write;
+use std::sync::LazyLock;
+use tempfile::TempDir;
+
+struct Get(&'static str);
+
+impl Request<Option<String>> for Get {
+    fn method(&self) -> &[u8] {
+        b"GET"
+    }
+
+    fn path(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+
+    fn first_header_value(&self, _key: &'static [u8]) -> Option<&[u8]> {
+        None
+    }
+
+    fn response<'a>(
+        self,
+        _code: StatusCode,
+        mut headers: impl Iterator<Item = &'a Line>,
+        _body: Option<&'a [u8]>,
+    ) -> Option<String> {
+        headers
+            .find(|line| line.key == b"content-type")
+            .map(|line| String::from_utf8(line.value.as_ref().to_vec()).unwrap())
+    }
+}
+
+// served in place of the uncompressed file
+fn brotli(content: &[u8]) -> Vec<u8> {
+    let mut writer = CompressorWriter::new(Vec::new(), 4096, 5, 22);
+    writer.write_all(content).unwrap();
+    writer.into_inner()
+}
+
+static ROOT: LazyLock<TempDir> = LazyLock::new(|| {
+    common::site(&[
+        ("page.html", "<p>é</p>".as_bytes()),
+        ("bom.txt", b"\xef\xbb\xbfbom"),
+        ("utf16.txt", b"\xff\xfet\0e\0x\0t\0"),
+        ("latin1.txt", b"caf\xe9"),
+        ("compressed.txt", b"caf\xe9"),
+        ("compressed.txt.br", &brotli(b"caf\xe9")),
+        ("data.csv", b"a,b"),
+        ("notes.md", b"# notes"),
+        ("data.json", b"{}"),
+        ("image.png", b"\x89PNG"),
+    ])
+});
+
+async fn content_type(charsets: Charsets, path: &'static str) -> Option<String> {
+    let handler = Handler {
+        root: Box::leak(ROOT.path().to_path_buf().into_boxed_path()),
+        prefix: "",
+        header_selector: DefaultHeaderSelector,
+        cors: None,
+        sniff: false,
+        dot_paths: DEFAULT_DOT_PATHS,
+        charsets: Some(Box::leak(Box::new(charsets))),
+        security: None,
+    };
+    handler.handle(Get(path)).await
+}
+
+fn rules(rules: &[&str]) -> Vec<CharsetRule> {
+    rules.iter().map(|it| it.parse().unwrap()).collect()
+}
+
+#[tokio::test]
+async fn overrides_the_charset_per_type() {
+    let charsets = || Charsets {
+        default: Some("iso-8859-1".to_string()),
+        rules: rules(&[
+            "text/csv=windows-1252",
+            "text/markdown=none",
+            "image/png=utf-8",
+        ]),
+        detect: false,
+    };
+    for (path, expected) in [
+        ("/page.html", "text/html; charset=iso-8859-1"),
+        ("/data.csv", "text/csv; charset=windows-1252"),
+        ("/notes.md", "text/markdown"),
+        ("/image.png", "image/png; charset=utf-8"),
+    ] {
+        assert_eq!(
+            content_type(charsets(), path).await.as_deref(),
+            Some(expected),
+            "{path}"
+        );
+    }
+    let charsets = Charsets {
+        default: None,
+        rules: vec![],
+        detect: false,
+    };
+    assert_eq!(
+        content_type(charsets, "/data.json").await.as_deref(),
+        Some("application/json")
+    );
+}
+
+#[tokio::test]
+async fn detects_the_charset_from_the_content() {
+    let charsets = || Charsets {
+        default: Some("utf-8".to_string()),
+        rules: vec![],
+        detect: true,
+    };
+    for (path, expected) in [
+        ("/page.html", "text/html; charset=utf-8"),
+        ("/bom.txt", "text/plain; charset=utf-8"),
+        ("/utf16.txt", "text/plain; charset=utf-16le"),
+        // not UTF-8, left for the browser to guess
+        ("/latin1.txt", "text/plain"),
+        ("/compressed.txt", "text/plain"),
+        ("/image.png", "image/png"),
+    ] {
+        assert_eq!(
+            content_type(charsets(), path).await.as_deref(),
+            Some(expected),
+            "{path}"
+        );
+    }
+}
+
+#[test]
+fn parses_rules() {
+    let rule: CharsetRule = "Text/CSV=Windows-1252".parse().unwrap();
+    assert_eq!(rule.content_type, "text/csv");
+    assert_eq!(rule.charset.as_deref(), Some("windows-1252"));
+    let rule: CharsetRule = "text/markdown=none".parse().unwrap();
+    assert_eq!(rule.charset, None);
+    assert!("text/csv".parse::<CharsetRule>().is_err());
+    assert!("csv=utf-8".parse::<CharsetRule>().is_err());
+    assert!("text/csv=".parse::<CharsetRule>().is_err());
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use serve::check::{Checked, check, site_paths, unmapped_files};
+use serve::handler::DEFAULT_DOT_PATHS;
+use std::fs;
+use tempfile::TempDir;
+
+fn site() -> TempDir {
+    common::site(&[
+        ("index.html", b"<h1>home</h1>"),
+        ("about.html", b"<h1>about</h1>"),
+        ("app.js", b"console.log(1)"),
+        ("app.js.br", b"compressed"),
+        ("old.307", b"/about"),
+        ("docs/guide.html", b"<h1>guide</h1>"),
+        (".env", b"SECRET=1"),
+        (".git/objects/ab/cdef", b"blob"),
+        ("fonts/icons.unknownext", b"font"),
+        ("fonts/icons.unknownext.br", b"compressed"),
+        ("README", b"readme"),
+    ])
+}
+
+#[test]
+fn lists_every_path_reaching_a_file() {
+    let root = site();
+    assert_eq!(
+        site_paths(root.path(), "", DEFAULT_DOT_PATHS).unwrap(),
+        [
+            "/",
+            "/README",
+            "/about",
+            "/about.html",
+            "/app.js",
+            "/docs/guide",
+            "/docs/guide.html",
+            "/fonts/icons.unknownext",
+            "/index",
+            "/index.html",
+            "/old",
+        ]
+    );
+    assert_eq!(
+        site_paths(root.path(), "app", DEFAULT_DOT_PATHS).unwrap()[0],
+        "/app/"
+    );
+}
+
+#[tokio::test]
+async fn resolves_paths_like_the_server() {
+    let root = site();
+    let checked = check(root.path(), "/app/", DEFAULT_DOT_PATHS)
+        .await
+        .unwrap();
+    let find = |path: &str| checked.iter().find(|it| it.path == path).unwrap();
+    assert_eq!(find("/app/").status, 200);
+    assert_eq!(
+        find("/app/about").header("content-type"),
+        Some("text/html; charset=utf-8")
+    );
+    assert_eq!(find("/app/app.js").header("content-encoding"), Some("br"));
+    assert_eq!(find("/app/app.js").length, 10);
+    assert_eq!(find("/app/old").status, 307);
+    assert_eq!(find("/app/old").header("location"), Some("/about"));
+    assert!(!checked.iter().any(|it| it.path == "/app/.env"));
+}
+
+#[test]
+fn lists_files_without_known_type() {
+    let root = site();
+    assert_eq!(
+        unmapped_files(root.path(), DEFAULT_DOT_PATHS).unwrap(),
+        ["README", "fonts/icons.unknownext"]
+    );
+}
+
+#[tokio::test]
+async fn follows_the_configured_dot_paths() {
+    let root = site();
+    fs::create_dir_all(root.path().join(".config")).unwrap();
+    fs::write(root.path().join(".config/app.json"), b"{}").unwrap();
+    fs::write(root.path().join(".config/notes.unknownext"), b"").unwrap();
+    assert_eq!(
+        unmapped_files(root.path(), &[".config"]).unwrap(),
+        [
+            ".config/notes.unknownext",
+            "README",
+            "fonts/icons.unknownext"
+        ]
+    );
+    let status = |checked: &[Checked]| {
+        checked
+            .iter()
+            .find(|it| it.path == "/.config/app.json")
+            .map(|it| it.status)
+    };
+    let checked = check(root.path(), "", &[".config"]).await.unwrap();
+    assert_eq!(status(&checked), Some(200));
+    let checked = check(root.path(), "", DEFAULT_DOT_PATHS).await.unwrap();
+    assert_eq!(status(&checked), None);
+}
@@ -0,0 +1,13 @@
+use std::fs;
+use tempfile::TempDir;
+
+// a temporary site root with the given files, creating their directories
+pub fn site(files: &[(&str, &[u8])]) -> TempDir {
+    let root = TempDir::new().unwrap();
+    for (path, content) in files {
+        let path = root.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    root
+}
@@ -0,0 +1,451 @@
+mod common;
+
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{CONNECTION, UPGRADE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode, Uri};
+use hyper_util::rt::TokioIo;
+use serve::forward::{
+    ForwardClient, ForwardOptions, ForwardPolicy, Proxy, Route, Upstream, UpstreamTls, client,
+    forward_uri, rewrite_cookie, rewrite_location,
+};
+use serve::{Server, ServerBuilder, ServerHandle};
+use std::convert::Infallible;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+// a plain http upstream answering /status/<code> with that status and the request headers
+// as the body, and switching to an echo protocol on upgrade
+async fn upstream() -> SocketAddr {
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .await
+        .unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(
+                http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service_fn(answer))
+                    .with_upgrades(),
+            );
+        }
+    });
+    address
+}
+
+async fn answer(mut request: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    if request.headers().contains_key(UPGRADE) {
+        let upgrade = hyper::upgrade::on(&mut request);
+        tokio::spawn(async move {
+            let mut upgraded = TokioIo::new(upgrade.await.unwrap());
+            let mut buf = [0u8; 64];
+            while let Ok(read @ 1..) = upgraded.read(&mut buf).await {
+                upgraded.write_all(&buf[..read]).await.unwrap();
+            }
+        });
+        return Ok(Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(CONNECTION, "upgrade")
+            .header(UPGRADE, "echo")
+            .body(Full::default())
+            .unwrap());
+    }
+    // x-reply-<name> request headers are sent back as <name>
+    let mut response = Response::builder();
+    for (name, value) in request.headers() {
+        if let Some(name) = name.as_str().strip_prefix("x-reply-") {
+            response = response.header(name, value);
+        }
+    }
+    let status = request
+        .uri()
+        .path()
+        .strip_prefix("/status/")
+        .and_then(|it| it.parse().ok())
+        .unwrap_or(200);
+    let headers = request
+        .headers()
+        .iter()
+        .map(|(name, value)| format!("{name}: {}\n", value.to_str().unwrap()))
+        .collect::<String>();
+    Ok(response
+        .status(status)
+        .header("x-upstream", "yes")
+        .body(Full::new(Bytes::from(headers)))
+        .unwrap())
+}
+
+fn options() -> Arc<ForwardOptions> {
+    Arc::new(ForwardOptions {
+        tls: UpstreamTls::new(false, false, &[]).unwrap(),
+        rewrite_host: false,
+        security_headers: false,
+        security: None,
+    })
+}
+
+// a dev server forwarding to the upstream, with static content for / and /status/404
+async fn start(
+    upstream: SocketAddr,
+    configure: impl FnOnce(ServerBuilder) -> ServerBuilder,
+) -> (TempDir, ServerHandle) {
+    let root = common::site(&[
+        ("index.html", b"<h1>home</h1>"),
+        ("status/404.html", b"<h1>static</h1>"),
+    ]);
+    let origin = format!("http://{upstream}").parse().unwrap();
+    let builder = Server::builder()
+        .address(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .root(root.path())
+        .forwarded(Upstream::new(origin, options()));
+    let server = configure(builder).start().await.unwrap();
+    (root, server)
+}
+
+// trusts the self-signed certificate of the dev server
+fn dev_client() -> ForwardClient {
+    client(&UpstreamTls::new(false, true, &[]).unwrap(), true)
+}
+
+fn empty() -> BoxBody<Bytes, hyper::Error> {
+    Empty::new().map_err(|it| match it {}).boxed()
+}
+
+fn url(server: &ServerHandle, path: &str) -> String {
+    format!("https://127.0.0.1:{}{path}", server.address().port())
+}
+
+#[test]
+fn rejects_routes_without_scheme() {
+    for origin in ["localhost:8080", "ftp://localhost/", "//localhost:8080"] {
+        let route = format!("/api={origin}");
+        assert!(route.parse::<Route>().is_err(), "{route}");
+    }
+    assert!("/api=http://localhost:8080".parse::<Route>().is_ok());
+    assert!("/api=https://localhost:8080/v1".parse::<Route>().is_ok());
+}
+
+#[test]
+fn forward_uri_fails_without_scheme() {
+    let path: Uri = "/api/items?q=1".parse().unwrap();
+    let origin: Uri = "https://localhost:8080".parse().unwrap();
+    assert_eq!(
+        forward_uri(&origin, &path).unwrap(),
+        "https://localhost:8080/api/items?q=1"
+    );
+    let origin: Uri = "localhost:8080".parse().unwrap();
+    assert!(forward_uri(&origin, &path).is_err());
+}
+
+// the dev server itself is a local upstream with a self-signed certificate
+#[tokio::test]
+async fn verifies_local_upstreams_unless_asked_not_to() {
+    let root = common::site(&[("index.html", b"<h1>home</h1>")]);
+    let mut server = Server::builder()
+        .address(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .root(root.path())
+        .start()
+        .await
+        .unwrap();
+    let status = |insecure, insecure_localhost| {
+        let url = format!("https://127.0.0.1:{}/", server.address().port());
+        async move {
+            let tls = UpstreamTls::new(insecure, insecure_localhost, &[]).unwrap();
+            let request = Request::get(url).body(empty()).unwrap();
+            client(&tls, false)
+                .request(request)
+                .await
+                .map(|it| it.status())
+        }
+    };
+    assert!(status(false, false).await.is_err());
+    assert_eq!(status(false, true).await.unwrap(), StatusCode::OK);
+    assert_eq!(status(true, false).await.unwrap(), StatusCode::OK);
+    server.shutdown(Duration::from_secs(1)).await;
+}
+
+// the vite HMR socket connects to a path the static content answers
+#[tokio::test]
+async fn tunnels_upgrades_to_the_upstream() {
+    let (_root, mut server) = start(upstream().await, |it| it).await;
+    let request = Request::get(url(&server, "/?token=abc"))
+        .header(CONNECTION, "upgrade")
+        .header(UPGRADE, "echo")
+        .body(empty())
+        .unwrap();
+    let response = dev_client().request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+    let mut upgraded = TokioIo::new(hyper::upgrade::on(response).await.unwrap());
+    upgraded.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    upgraded.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    server.shutdown(Duration::from_secs(1)).await;
+}
+
+// the status and which side answered a GET or a POST
+async fn fetch(server: &ServerHandle, method: &str, path: &str) -> (u16, &'static str) {
+    let request = Request::builder()
+        .method(method)
+        .uri(url(server, path))
+        .body(empty())
+        .unwrap();
+    let response = dev_client().request(request).await.unwrap();
+    let side = if response.headers().contains_key("x-upstream") {
+        "upstream"
+    } else {
+        "static"
+    };
+    (response.status().as_u16(), side)
+}
+
+#[tokio::test]
+async fn forwards_static_errors_by_default() {
+    let (_root, mut server) = start(upstream().await, |it| it).await;
+    assert_eq!(fetch(&server, "GET", "/").await, (200, "static"));
+    assert_eq!(fetch(&server, "GET", "/missing").await, (200, "upstream"));
+    assert_eq!(fetch(&server, "POST", "/").await, (200, "upstream"));
+    assert_eq!(
+        fetch(&server, "GET", "/status/500").await,
+        (500, "upstream")
+    );
+    server.shutdown(Duration::from_secs(1)).await;
+    let (_root, mut server) = start(upstream().await, |it| {
+        it.forward_on(vec!["404".parse().unwrap()])
+    })
+    .await;
+    assert_eq!(fetch(&server, "POST", "/").await, (405, "static"));
+    assert_eq!(fetch(&server, "GET", "/missing").await, (200, "upstream"));
+    server.shutdown(Duration::from_secs(1)).await;
+}
+
+#[tokio::test]
+async fn keeps_static_errors_when_the_upstream_fails_too() {
+    let (_root, mut server) = start(upstream().await, |it| it.static_on_upstream_error(true)).await;
+    assert_eq!(fetch(&server, "GET", "/missing").await, (200, "upstream"));
+    assert_eq!(fetch(&server, "GET", "/status/500").await, (404, "static"));
+    assert_eq!(fetch(&server, "GET", "/status/410").await, (404, "static"));
+    server.shutdown(Duration::from_secs(1)).await;
+}
+
+#[tokio::test]
+async fn falls_back_to_static_content_with_proxy_first() {
+    let (_root, mut server) = start(upstream().await, |it| {
+        it.forward_policy(ForwardPolicy::ProxyFirst)
+    })
+    .await;
+    assert_eq!(fetch(&server, "GET", "/").await, (200, "upstream"));
+    assert_eq!(fetch(&server, "GET", "/status/404").await, (200, "static"));
+    assert_eq!(
+        fetch(&server, "GET", "/status/500").await,
+        (500, "upstream")
+    );
+    assert_eq!(
+        fetch(&server, "POST", "/status/405").await,
+        (405, "upstream")
+    );
+    server.shutdown(Duration::from_secs(1)).await;
+    let (_root, mut server) = start(upstream().await, |it| {
+        it.forward_policy(ForwardPolicy::ProxyFirst)
+            .fallback_on(vec!["4xx".parse().unwrap(), "5xx".parse().unwrap()])
+    })
+    .await;
+    assert_eq!(fetch(&server, "GET", "/status/500").await, (404, "static"));
+    assert_eq!(fetch(&server, "POST", "/status/405").await, (405, "static"));
+    server.shutdown(Duration::from_secs(1)).await;
+    // an unreachable upstream falls back too
+    let closed = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let (_root, mut server) =
+        start(closed, |it| it.forward_policy(ForwardPolicy::ProxyFirst)).await;
+    assert_eq!(fetch(&server, "GET", "/").await, (200, "static"));
+    server.shutdown(Duration::from_secs(1)).await;
+}
+
+#[tokio::test]
+async fn adds_forwarding_headers() {
+    let (_root, mut server) = start(upstream().await, |it| it).await;
+    let request = Request::get(url(&server, "/status/200"))
+        .header("forwarded", "for=192.0.2.1")
+        .header("x-forwarded-for", "192.0.2.1")
+        .body(empty())
+        .unwrap();
+    let response = dev_client().request(request).await.unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let headers = String::from_utf8(body.to_vec()).unwrap();
+    let host = format!("127.0.0.1:{}", server.address().port());
+    for expected in [
+        format!("forwarded: for=192.0.2.1, for=127.0.0.1;proto=https;host=\"{host}\""),
+        "x-forwarded-for: 192.0.2.1, 127.0.0.1".to_string(),
+        "x-forwarded-proto: https".to_string(),
+        format!("x-forwarded-host: {host}"),
+    ] {
+        assert!(
+            headers.lines().any(|it| it == expected),
+            "{expected}\n{headers}"
+        );
+    }
+    server.shutdown(Duration::from_secs(1)).await;
+}
+
+// clients on IPv6 are quoted in Forwarded
+#[tokio::test]
+async fn quotes_ipv6_clients() {
+    let upstream = upstream().await;
+    let (_root, mut server) = start(upstream, |it| {
+        it.address(SocketAddr::from((Ipv6Addr::LOCALHOST, 0)))
+    })
+    .await;
+    let request = Request::get(format!(
+        "https://[::1]:{}/status/200",
+        server.address().port()
+    ))
+    .body(empty())
+    .unwrap();
+    let response = dev_client().request(request).await.unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let headers = String::from_utf8(body.to_vec()).unwrap();
+    let expected = format!(
+        "forwarded: for=\"[::1]\";proto=https;host=\"[::1]:{}\"",
+        server.address().port()
+    );
+    assert!(headers.lines().any(|it| it == expected), "{headers}");
+    assert!(headers.lines().any(|it| it == "x-forwarded-for: ::1"));
+    server.shutdown(Duration::from_secs(1)).await;
+}
+
+fn route(value: &str) -> Route {
+    value.parse().unwrap()
+}
+
+#[test]
+fn maps_upstream_paths_back_through_routes() {
+    let api = route("/api=http://127.0.0.1:3000/v1");
+    assert_eq!(api.restore_path("/v1/items"), "/api/items");
+    assert_eq!(api.restore_path("/v1"), "/api");
+    assert_eq!(api.restore_path("/v1/"), "/api");
+    // outside the rewritten path
+    assert_eq!(api.restore_path("/v10/items"), "/v10/items");
+    assert_eq!(api.restore_path("/other"), "/other");
+    let uri = |it: &str| it.parse::<Uri>().unwrap();
+    assert_eq!(api.rewrite_uri(&uri("/api/items?q=1")), "/v1/items?q=1");
+    assert_eq!(api.rewrite_uri(&uri("/api")), "/v1");
+    // the whole site under a path
+    let root = route("/=http://127.0.0.1:3000/v1");
+    assert_eq!(root.rewrite_uri(&uri("/items?q=1")), "/v1/items?q=1");
+    assert_eq!(root.restore_path("/v1/items"), "/items");
+    assert_eq!(root.restore_path("/v1"), "/");
+    // an empty rewrite strips the prefix
+    let strip = route("/api=http://127.0.0.1:3000/");
+    assert_eq!(strip.rewrite.as_deref(), Some(""));
+    assert_eq!(strip.rewrite_uri(&uri("/api/items?q=1")), "/items?q=1");
+    assert_eq!(strip.rewrite_uri(&uri("/api")), "/");
+    assert_eq!(strip.restore_path("/items"), "/api/items");
+    assert_eq!(strip.restore_path("/"), "/api");
+    // no path on the origin, nothing to rewrite
+    let same = route("/api=http://127.0.0.1:3000");
+    assert_eq!(same.rewrite, None);
+    assert_eq!(same.rewrite_uri(&uri("/api/items")), "/api/items");
+    assert_eq!(same.restore_path("/api/items"), "/api/items");
+}
+
+#[test]
+fn rewrites_upstream_locations() {
+    let upstream = Some("http://127.0.0.1:3000");
+    let public = Some("https://localhost:8443");
+    let api = route("/api=http://127.0.0.1:3000/v1");
+    let location = |value, route| rewrite_location(value, upstream, public, route);
+    assert_eq!(
+        location("http://127.0.0.1:3000/v1/items?q=1#top", Some(&api)).as_deref(),
+        Some("https://localhost:8443/api/items?q=1#top")
+    );
+    assert_eq!(
+        location("HTTP://127.0.0.1:3000", None).as_deref(),
+        Some("https://localhost:8443")
+    );
+    assert_eq!(
+        location("/v1/items?next=/v1/other#/v1", Some(&api)).as_deref(),
+        Some("/api/items?next=/v1/other#/v1")
+    );
+    assert_eq!(location("/login?q=1", None).as_deref(), Some("/login?q=1"));
+    // elsewhere
+    assert_eq!(location("https://other.example/v1", Some(&api)), None);
+    assert_eq!(location("http://127.0.0.1:30001/v1", Some(&api)), None);
+    assert_eq!(location("//other.example/v1", Some(&api)), None);
+    assert_eq!(location("items", Some(&api)), None);
+    // the public origin is unknown without a host
+    assert_eq!(
+        rewrite_location("http://127.0.0.1:3000/v1", upstream, None, Some(&api)),
+        None
+    );
+}
+
+#[test]
+fn rewrites_upstream_cookies() {
+    let api = route("/api=http://127.0.0.1:3000/v1");
+    assert_eq!(
+        rewrite_cookie(
+            "id=1; Domain=api.example.com; Path=/v1; Secure",
+            Some("localhost"),
+            Some(&api)
+        ),
+        "id=1; Path=/api; Secure"
+    );
+    assert_eq!(
+        rewrite_cookie(
+            "id=1; domain=LOCALHOST; path=/v1/admin",
+            Some("localhost"),
+            Some(&api)
+        ),
+        "id=1; domain=LOCALHOST; Path=/api/admin"
+    );
+    // without a route the path is the same on both sides
+    assert_eq!(
+        rewrite_cookie("id=1; Path=/v1; HttpOnly", Some("localhost"), None),
+        "id=1; Path=/v1; HttpOnly"
+    );
+    assert_eq!(rewrite_cookie("id=1; Domain=localhost", None, None), "id=1");
+    let strip = route("/api=http://127.0.0.1:3000/");
+    assert_eq!(
+        rewrite_cookie("id=1; Path=/", Some("localhost"), Some(&strip)),
+        "id=1; Path=/api"
+    );
+}
+
+#[tokio::test]
+async fn rewrites_proxied_responses() {
+    let upstream = upstream().await;
+    let api = route(&format!("/api=http://{upstream}/v1"));
+    let (_root, mut server) = start(upstream, |it| it.proxy(Proxy::new(api, options()))).await;
+    let request = Request::get(url(&server, "/api/items?q=1"))
+        .header(
+            "x-reply-location",
+            format!("http://{upstream}/v1/login?next=1"),
+        )
+        .header(
+            "x-reply-set-cookie",
+            "id=1; Domain=api.example.com; Path=/v1",
+        )
+        .body(empty())
+        .unwrap();
+    let response = dev_client().request(request).await.unwrap();
+    assert_eq!(
+        response.headers()["location"],
+        format!(
+            "https://127.0.0.1:{}/api/login?next=1",
+            server.address().port()
+        )
+    );
+    assert_eq!(response.headers()["set-cookie"], "id=1; Path=/api");
+    server.shutdown(Duration::from_secs(1)).await;
+}
@@ -0,0 +1,574 @@
+mod common;
+
+use serve::cors::Cors;
+use serve::handler::{DEFAULT_DOT_PATHS, Handler};
+use serve::http::headers::Line;
+use serve::http::request::Request;
+use serve::http::response::StatusCode;
+use serve::security::{Isolation, Security};
+use serve::types::DefaultHeaderSelector;
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+use tempfile::TempDir;
+
+struct FakeRequest {
+    method: &'static [u8],
+    path: &'static str,
+    headers: Vec<(&'static [u8], Vec<u8>)>,
+    sniffed: Option<String>,
+}
+
+struct FakeResponse {
+    status: u16,
+    headers: Vec<(Vec<u8>, Vec<u8>)>,
+    body: Option<Vec<u8>>,
+    sniffed: Option<String>,
+}
+
+impl FakeRequest {
+    fn get(path: &'static str) -> Self {
+        Self {
+            method: b"GET",
+            path,
+            headers: vec![],
+            sniffed: None,
+        }
+    }
+
+    fn head(path: &'static str) -> Self {
+        Self {
+            method: b"HEAD",
+            ..Self::get(path)
+        }
+    }
+
+    fn method(method: &'static [u8], path: &'static str) -> Self {
+        Self {
+            method,
+            ..Self::get(path)
+        }
+    }
+
+    fn header(mut self, key: &'static [u8], value: impl AsRef<[u8]>) -> Self {
+        self.headers.push((key, value.as_ref().to_vec()));
+        self
+    }
+}
+
+impl Request<FakeResponse> for FakeRequest {
+    fn method(&self) -> &[u8] {
+        self.method
+    }
+
+    fn path(&self) -> &[u8] {
+        self.path.as_bytes()
+    }
+
+    fn first_header_value(&self, key: &'static [u8]) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_slice())
+    }
+
+    fn response<'b>(
+        self,
+        code: StatusCode,
+        headers: impl Iterator<Item = &'b Line>,
+        body: Option<&'b [u8]>,
+    ) -> FakeResponse {
+        FakeResponse {
+            status: code.into(),
+            headers: headers
+                .map(|line| (line.key.to_vec(), line.value.as_ref().to_vec()))
+                .collect(),
+            body: body.map(|it| it.to_vec()),
+            sniffed: self.sniffed,
+        }
+    }
+
+    fn sniffed(&mut self, path: &str) {
+        self.sniffed = Some(path.to_string());
+    }
+}
+
+impl FakeResponse {
+    fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name == key.as_bytes())
+            .map(|(_, value)| std::str::from_utf8(value).unwrap())
+    }
+
+    fn body(&self) -> &[u8] {
+        self.body.as_deref().unwrap_or_default()
+    }
+}
+
+// shared by every test, the handler only reads it
+static ROOT: LazyLock<TempDir> = LazyLock::new(|| {
+    common::site(&[
+        ("index.html", b"<h1>home</h1>"),
+        ("page.html", b"<h1>page</h1>"),
+        ("style.css", b"body { color: red }"),
+        ("style.css.br", b"compressed css"),
+        ("app.js", b"console.log(1)"),
+        ("image.png", b"\x89PNG"),
+        ("image.png.br", b"should not be used"),
+        ("docs/guide.html", b"<h1>guide</h1>"),
+        ("docs/index.html", b"<h1>docs</h1>"),
+        ("old.307", b"/new\n"),
+        ("moved.308", b"https://example.com/moved trailing text"),
+        ("both.html", b"<h1>both</h1>"),
+        ("both.307", b"/elsewhere"),
+        (".secret", b"secret"),
+        ("dir/.hidden/page.html", b"hidden"),
+        ("data.unknownext", b"data"),
+        ("blob", b"\x89PNG\r\n\x1a\nrest"),
+        ("apple-app-site-association", b"{ \"applinks\": {} }"),
+        (".well-known/apple-app-site-association", b"{}"),
+        (
+            ".well-known/security.txt",
+            b"Contact: mailto:security@example.com",
+        ),
+        (".well-known/acme-challenge/token", b"token.thumbprint"),
+        (".well-known/.hidden", b"hidden"),
+        (".config/settings.json", b"{}"),
+    ])
+});
+
+fn handler(prefix: &'static str) -> Handler<DefaultHeaderSelector> {
+    Handler {
+        root: Box::leak(ROOT.path().to_path_buf().into_boxed_path()),
+        prefix,
+        header_selector: DefaultHeaderSelector,
+        cors: None,
+        sniff: false,
+        dot_paths: DEFAULT_DOT_PATHS,
+        charsets: None,
+        security: None,
+    }
+}
+
+async fn handle(request: FakeRequest) -> FakeResponse {
+    handler("").handle(request).await
+}
+
+fn etag(content: &[u8]) -> String {
+    format!("{:x}", crc32fast::hash(content))
+}
+
+#[tokio::test]
+async fn serves_index_for_root() {
+    let response = handle(FakeRequest::get("/")).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body(), b"<h1>home</h1>");
+    assert_eq!(
+        response.header("content-type"),
+        Some("text/html; charset=utf-8")
+    );
+    assert_eq!(response.header("content-length"), Some("13"));
+    assert_eq!(
+        response.header("etag"),
+        Some(etag(b"<h1>home</h1>").as_str())
+    );
+    assert!(response.header("content-security-policy").is_some());
+}
+
+#[tokio::test]
+async fn head_has_headers_without_body() {
+    let response = handle(FakeRequest::head("/page.html")).await;
+    assert_eq!(response.status, 200);
+    assert!(response.body.is_none());
+    assert_eq!(response.header("content-length"), Some("13"));
+    assert!(response.header("etag").is_some());
+}
+
+#[tokio::test]
+async fn resolves_html_extension() {
+    let response = handle(FakeRequest::get("/page")).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body(), b"<h1>page</h1>");
+    let response = handle(FakeRequest::get("/docs/guide")).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body(), b"<h1>guide</h1>");
+}
+
+#[tokio::test]
+async fn prefers_brotli_for_compressible_types() {
+    let response = handle(FakeRequest::get("/style.css")).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-encoding"), Some("br"));
+    assert_eq!(response.body(), b"compressed css");
+    assert_eq!(response.header("content-length"), Some("14"));
+    assert_eq!(
+        response.header("etag"),
+        Some(etag(b"compressed css").as_str())
+    );
+}
+
+#[tokio::test]
+async fn serves_uncompressed_without_brotli_sibling() {
+    let response = handle(FakeRequest::get("/app.js")).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-encoding"), None);
+    assert_eq!(response.body(), b"console.log(1)");
+}
+
+#[tokio::test]
+async fn ignores_brotli_for_incompressible_types() {
+    let response = handle(FakeRequest::get("/image.png")).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-encoding"), None);
+    assert_eq!(response.body(), b"\x89PNG");
+}
+
+#[tokio::test]
+async fn if_none_match_with_current_etag_is_not_modified() {
+    let response =
+        handle(FakeRequest::get("/page").header(b"if-none-match", etag(b"<h1>page</h1>"))).await;
+    assert_eq!(response.status, 304);
+    assert!(response.body.is_none());
+    let response = handle(FakeRequest::get("/page").header(b"if-none-match", "stale")).await;
+    assert_eq!(response.status, 200);
+}
+
+#[tokio::test]
+async fn if_match_with_other_etag_fails_precondition() {
+    let response = handle(FakeRequest::get("/page").header(b"if-match", "stale")).await;
+    assert_eq!(response.status, 412);
+    let response =
+        handle(FakeRequest::get("/page").header(b"if-match", etag(b"<h1>page</h1>"))).await;
+    assert_eq!(response.status, 200);
+}
+
+#[tokio::test]
+async fn trailing_slash_redirects_permanently() {
+    let response = handle(FakeRequest::get("/docs/")).await;
+    assert_eq!(response.status, 308);
+    assert_eq!(response.header("location"), Some("/docs"));
+    let response = handle(FakeRequest::get("/missing/")).await;
+    assert_eq!(response.status, 308);
+    assert_eq!(response.header("location"), Some("/missing"));
+}
+
+#[tokio::test]
+async fn hides_dotfiles() {
+    assert_eq!(handle(FakeRequest::get("/.secret")).await.status, 404);
+    assert_eq!(
+        handle(FakeRequest::get("/dir/.hidden/page.html"))
+            .await
+            .status,
+        404
+    );
+    assert_eq!(
+        handle(FakeRequest::get("/dir/.hidden/page")).await.status,
+        404
+    );
+}
+
+#[tokio::test]
+async fn temporary_redirect_from_307_file() {
+    let response = handle(FakeRequest::get("/old")).await;
+    assert_eq!(response.status, 307);
+    assert_eq!(response.header("location"), Some("/new"));
+    assert_eq!(response.header("content-length"), Some("0"));
+    assert!(response.header("etag").is_some());
+    assert!(response.body.is_none());
+}
+
+#[tokio::test]
+async fn prefers_content_over_redirect() {
+    let response = handle(FakeRequest::get("/both")).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body(), b"<h1>both</h1>");
+}
+
+#[tokio::test]
+async fn permanent_redirect_from_308_file_stops_at_whitespace() {
+    let response = handle(FakeRequest::get("/moved")).await;
+    assert_eq!(response.status, 308);
+    assert_eq!(
+        response.header("location"),
+        Some("https://example.com/moved")
+    );
+    assert_eq!(response.header("etag"), None);
+}
+
+#[tokio::test]
+async fn missing_and_unmapped_files_are_not_found() {
+    for path in ["/missing", "/missing.html", "/data.unknownext"] {
+        let response = handle(FakeRequest::get(path)).await;
+        assert_eq!(response.status, 404, "{path}");
+        assert_eq!(response.header("content-length"), Some("0"));
+        assert_eq!(response.header("allow"), Some("GET, HEAD"));
+    }
+}
+
+#[tokio::test]
+async fn rejects_other_methods() {
+    let response = handle(FakeRequest::method(b"POST", "/")).await;
+    assert_eq!(response.status, 405);
+    assert_eq!(response.header("allow"), Some("GET, HEAD"));
+    let response = handle(FakeRequest::method(b"OPTIONS", "/")).await;
+    assert_eq!(response.status, 405);
+}
+
+#[tokio::test]
+async fn rejects_request_bodies() {
+    let response = handle(FakeRequest::get("/").header(b"content-length", "3")).await;
+    assert_eq!(response.status, 400);
+    let response = handle(FakeRequest::get("/").header(b"content-length", "0")).await;
+    assert_eq!(response.status, 200);
+    let response = handle(FakeRequest::get("/").header(b"transfer-encoding", "chunked")).await;
+    assert_eq!(response.status, 400);
+}
+
+#[tokio::test]
+async fn serves_under_prefix_only() {
+    let handler = handler("app");
+    let response = handler.handle(FakeRequest::get("/app/page")).await;
+    assert_eq!(response.status, 200);
+    let response = handler.handle(FakeRequest::get("/app")).await;
+    assert_eq!(response.body(), b"<h1>home</h1>");
+    let response = handler.handle(FakeRequest::get("/page")).await;
+    assert_eq!(response.status, 404);
+    let response = handler.handle(FakeRequest::get("/app/docs/")).await;
+    assert_eq!(response.status, 308);
+    assert_eq!(response.header("location"), Some("/app/docs"));
+}
+
+#[tokio::test]
+async fn reads_files_from_root_not_working_directory() {
+    assert_ne!(std::env::current_dir().unwrap(), ROOT.path());
+    assert!(!Path::new("page.html").exists());
+    assert_eq!(handle(FakeRequest::get("/page")).await.status, 200);
+}
+
+#[tokio::test]
+async fn cors_headers_and_preflight() {
+    let handler = Handler {
+        cors: Some(Box::leak(Box::new(Cors {
+            origins: vec!["https://app.example".to_string()],
+            methods: vec![],
+            headers: vec![],
+            credentials: true,
+            max_age: Some(600),
+        }))),
+        ..handler("")
+    };
+    let response = handler
+        .handle(FakeRequest::get("/page").header(b"origin", "https://app.example"))
+        .await;
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("access-control-allow-origin"),
+        Some("https://app.example")
+    );
+    assert_eq!(
+        response.header("access-control-allow-credentials"),
+        Some("true")
+    );
+    let response = handler
+        .handle(FakeRequest::get("/missing").header(b"origin", "https://app.example"))
+        .await;
+    assert_eq!(response.status, 404);
+    assert!(response.header("access-control-allow-origin").is_some());
+    let response = handler
+        .handle(FakeRequest::get("/page").header(b"origin", "https://other.example"))
+        .await;
+    assert_eq!(response.header("access-control-allow-origin"), None);
+    let response = handler
+        .handle(
+            FakeRequest::method(b"OPTIONS", "/page")
+                .header(b"origin", "https://app.example")
+                .header(b"access-control-request-method", "GET")
+                .header(b"access-control-request-headers", "x-token"),
+        )
+        .await;
+    assert_eq!(response.status, 204);
+    assert_eq!(
+        response.header("access-control-allow-methods"),
+        Some("GET, HEAD, OPTIONS")
+    );
+    assert_eq!(
+        response.header("access-control-allow-headers"),
+        Some("x-token")
+    );
+    assert_eq!(response.header("access-control-max-age"), Some("600"));
+    let response = handler.handle(FakeRequest::method(b"POST", "/page")).await;
+    assert_eq!(response.status, 405);
+    assert_eq!(response.header("allow"), Some("GET, HEAD, OPTIONS"));
+}
+
+#[tokio::test]
+async fn explains_not_found_for_unmapped_extensions() {
+    let root = handler("");
+    assert_eq!(
+        root.unmapped_file(b"/data.unknownext").await.as_deref(),
+        Some("data.unknownext")
+    );
+    assert_eq!(root.unmapped_file(b"/missing.unknownext").await, None);
+    assert_eq!(root.unmapped_file(b"/page.html").await, None);
+    assert_eq!(root.unmapped_file(b"/.secret").await, None);
+    assert_eq!(root.unmapped_file(b"/docs").await, None);
+    let prefixed = handler("app");
+    assert_eq!(
+        prefixed
+            .unmapped_file(b"/app/data.unknownext")
+            .await
+            .as_deref(),
+        Some("data.unknownext")
+    );
+    assert_eq!(prefixed.unmapped_file(b"/data.unknownext").await, None);
+}
+
+#[tokio::test]
+async fn sniffs_files_without_known_extension_when_enabled() {
+    assert_eq!(handle(FakeRequest::get("/blob")).await.status, 404);
+    let handler = Handler {
+        sniff: true,
+        ..handler("")
+    };
+    let response = handler.handle(FakeRequest::get("/blob")).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-type"), Some("image/png"));
+    assert_eq!(response.body(), b"\x89PNG\r\n\x1a\nrest");
+    assert_eq!(response.sniffed.as_deref(), Some("blob"));
+    let response = handler
+        .handle(FakeRequest::get("/apple-app-site-association"))
+        .await;
+    assert_eq!(
+        response.header("content-type"),
+        Some("application/json; charset=utf-8")
+    );
+    assert_eq!(
+        response.sniffed.as_deref(),
+        Some("apple-app-site-association")
+    );
+    // not recognised
+    let response = handler.handle(FakeRequest::get("/data.unknownext")).await;
+    assert_eq!(response.status, 404);
+    assert_eq!(response.sniffed, None);
+    // known extensions are never sniffed
+    let response = handler.handle(FakeRequest::get("/image.png")).await;
+    assert_eq!(response.header("content-type"), Some("image/png"));
+    assert_eq!(response.sniffed, None);
+}
+
+#[tokio::test]
+async fn serves_well_known_despite_the_dotfile_rule() {
+    let response = handle(FakeRequest::get("/.well-known/apple-app-site-association")).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("content-type"),
+        Some("application/json; charset=utf-8")
+    );
+    let response = handle(FakeRequest::get("/.well-known/security.txt")).await;
+    assert_eq!(
+        response.header("content-type"),
+        Some("text/plain; charset=utf-8")
+    );
+    let response = handle(FakeRequest::get("/.well-known/acme-challenge/token")).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("content-type"),
+        Some("text/plain; charset=utf-8")
+    );
+    for path in [
+        "/.well-known/.hidden",
+        "/.config/settings.json",
+        "/.well-known/../.secret",
+    ] {
+        assert_eq!(handle(FakeRequest::get(path)).await.status, 404, "{path}");
+    }
+    let handler = Handler {
+        dot_paths: &[".config"],
+        ..handler("")
+    };
+    let response = handler
+        .handle(FakeRequest::get("/.config/settings.json"))
+        .await;
+    assert_eq!(response.status, 200);
+    let response = handler
+        .handle(FakeRequest::get("/.well-known/security.txt"))
+        .await;
+    assert_eq!(response.status, 404);
+}
+
+#[tokio::test]
+async fn sends_the_configured_security_policies() {
+    let response = handle(FakeRequest::get("/page")).await;
+    assert_eq!(
+        response.header("cross-origin-embedder-policy"),
+        Some("credentialless")
+    );
+    assert_eq!(
+        response.header("cross-origin-opener-policy"),
+        Some("same-origin")
+    );
+    assert!(response.header("content-security-policy").is_some());
+    let strict = Handler {
+        security: Some(Box::leak(Box::new(Security {
+            isolation: Isolation::RequireCorp,
+            csp: "none".parse().unwrap(),
+            csp_report_only: Some("{strict}; report-uri /csp".parse().unwrap()),
+        }))),
+        ..handler("")
+    };
+    for path in ["/page", "/old", "/docs/"] {
+        let response = strict.handle(FakeRequest::get(path)).await;
+        assert_eq!(
+            response.header("cross-origin-embedder-policy"),
+            Some("require-corp"),
+            "{path}"
+        );
+        assert_eq!(response.header("content-security-policy"), None, "{path}");
+        assert!(
+            response
+                .header("content-security-policy-report-only")
+                .unwrap()
+                .ends_with("; report-uri /csp"),
+            "{path}"
+        );
+    }
+    let not_isolated = Handler {
+        security: Some(Box::leak(Box::new(Security {
+            isolation: Isolation::None,
+            ..Security::default()
+        }))),
+        ..handler("")
+    };
+    let response = not_isolated.handle(FakeRequest::get("/page")).await;
+    assert_eq!(response.header("cross-origin-embedder-policy"), None);
+    assert!(response.header("content-security-policy").is_some());
+}
+
+#[tokio::test]
+async fn never_serves_files_outside_the_root() {
+    // not a dot directory, which would be hidden anyway
+    let outside = tempfile::Builder::new()
+        .prefix("outside")
+        .tempdir()
+        .unwrap();
+    fs::write(outside.path().join("outside.txt"), b"outside").unwrap();
+    fs::write(outside.path().join("outside.unknownext"), b"outside").unwrap();
+    let absolute = outside.path().join("outside.txt");
+    let absolute = absolute.to_str().unwrap();
+    let prefixed = handler("app");
+    for (handler, path) in [
+        (handler(""), format!("//{absolute}")),
+        (handler(""), format!("/{absolute}")),
+        (prefixed, format!("/app//{absolute}")),
+        (handler(""), "/docs/../page.html".to_string()),
+    ] {
+        let path: &'static str = path.leak();
+        let response = handler.handle(FakeRequest::get(path)).await;
+        assert_eq!(response.status, 404, "{path}");
+        assert_eq!(response.body(), b"", "{path}");
+    }
+    let unknown = outside.path().join("outside.unknownext");
+    let unknown = format!("//{}", unknown.to_str().unwrap());
+    assert_eq!(handler("").unmapped_file(unknown.as_bytes()).await, None);
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::site;
+use serve::handler::DEFAULT_DOT_PATHS;
+use serve::lint::{links, lint};
+use std::io::Write;
+
+fn brotli(content: &[u8]) -> Vec<u8> {
+    let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+    writer.write_all(content).unwrap();
+    writer.into_inner()
+}
+
+#[test]
+fn extracts_href_and_src_attributes() {
+    let html = "<a href=\"/a?x=1&amp;y=2\">a</a>\n<img\n  SRC = 'b.png' data-src=\"no\">\
+        <script src=c.js></script><p>href=\"text\"</p>";
+    assert_eq!(
+        links(html),
+        [
+            (1, "/a?x=1&y=2".to_string()),
+            (3, "b.png".to_string()),
+            (3, "c.js".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn reports_what_would_break() {
+    let root = site(&[
+        (
+            "index.html",
+            b"<a href=\"docs/guide\">ok</a>\n<a href=\"/missing\">x</a>\n\
+              <a href=\"https://example.com/missing\">ext</a><img src=\"img/a.png\">",
+        ),
+        (
+            "docs/guide.html",
+            b"<a href=\"../index.html#top\">up</a> <a href=\"../old\">old</a>",
+        ),
+        ("img/a.png", b"png"),
+        ("old.307", b"/docs/guide"),
+        ("chain.307", b"/old"),
+        ("loop.308", b"/loop"),
+        ("broken.307", b"/nowhere"),
+        ("notes.unknownext", b""),
+        ("app.js", b"console.log(1)"),
+        ("app.js.br", &brotli(b"console.log(2)")),
+        ("ok.css", b"a{}"),
+        ("ok.css.br", &brotli(b"a{}")),
+        (".env", b"ignored.unknownext"),
+    ]);
+    let issues = lint(root.path(), "", DEFAULT_DOT_PATHS).await.unwrap();
+    let issues: Vec<String> = issues
+        .iter()
+        .map(|it| match it.line {
+            Some(line) => format!("{}:{line} {}", it.file, it.message),
+            None => format!("{} {}", it.file, it.message),
+        })
+        .collect();
+    assert_eq!(
+        issues,
+        [
+            "app.js.br does not match app.js",
+            "broken.307 redirect to a missing path /broken -> /nowhere",
+            "chain.307 redirect chain /chain -> /old -> /docs/guide",
+            "index.html:2 broken link to /missing",
+            "loop.308 redirect loop /loop -> /loop",
+            "notes.unknownext unknown extension, the file is never served",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn resolves_links_under_prefix() {
+    let root = site(&[
+        (
+            "index.html",
+            b"<a href=\"/app/page\">a</a><a href=\"page\">b</a>",
+        ),
+        ("page.html", b"<a href=\"/page\">outside the prefix</a>"),
+    ]);
+    let issues = lint(root.path(), "/app/", DEFAULT_DOT_PATHS).await.unwrap();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].file, "page.html");
+}
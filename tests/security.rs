@@ -0,0 +1,45 @@
+use serve::security::CspPolicy;
+
+fn policy(value: &str) -> Result<Option<String>, String> {
+    value
+        .parse::<CspPolicy>()
+        .map(|it| it.policy().map(|it| it.to_string()))
+}
+
+#[test]
+fn parses_presets_and_templates() {
+    let strict = policy("strict").unwrap().unwrap();
+    assert!(strict.starts_with("default-src 'self';"));
+    assert!(!strict.contains("'unsafe-inline'"));
+    assert!(
+        policy("default")
+            .unwrap()
+            .unwrap()
+            .contains("'unsafe-inline'")
+    );
+    assert_eq!(policy("none").unwrap(), None);
+    assert_eq!(
+        policy("{strict}; report-uri /csp").unwrap().unwrap(),
+        format!("{strict}; report-uri /csp")
+    );
+    assert_eq!(
+        policy("{none}; img-src *").unwrap().as_deref(),
+        Some("img-src *")
+    );
+    assert_eq!(
+        policy("default-src 'none'").unwrap().as_deref(),
+        Some("default-src 'none'")
+    );
+    assert_eq!(
+        policy("upgrade-insecure-requests").unwrap().as_deref(),
+        Some("upgrade-insecure-requests")
+    );
+}
+
+#[test]
+fn rejects_unknown_presets() {
+    assert!(policy("stricter").is_err());
+    assert!(policy("{stricter}; img-src *").is_err());
+    assert!(policy("{strict; img-src *").is_err());
+    assert!(policy("default-src 'self'\nimg-src *").is_err());
+}
@@ -0,0 +1,242 @@
+mod common;
+
+use brotli::CompressorWriter;
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Bytes;
+use hyper::header::{CONTENT_TYPE, ETAG, IF_NONE_MATCH, LOCATION};
+use hyper::{Request, StatusCode};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+use serve::har::Recorder;
+use serve::log::{AccessLog, LogFormat};
+use serve::{Server, ServerHandle};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::sleep;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+type TestClient = Client<HttpsConnector<HttpConnector>, Empty<Bytes>>;
+
+async fn start() -> (TempDir, ServerHandle) {
+    let root = common::site(&[
+        ("index.html", b"<h1>home</h1>"),
+        ("style.css", b"body {}"),
+        ("style.css.br", b"compressed"),
+    ]);
+    let server = Server::builder()
+        .address(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .root(root.path())
+        .access_log(
+            AccessLog::new(LogFormat::Json, Some(&root.path().join(".access.log"))).unwrap(),
+        )
+        .start()
+        .await
+        .unwrap();
+    (root, server)
+}
+
+// trusts only the certificate generated by the server
+fn client(server: &ServerHandle) -> TestClient {
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(CertificateDer::from(server.certificate().to_vec()))
+        .unwrap();
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connector = HttpsConnectorBuilder::new()
+        .with_tls_config(config)
+        .https_only()
+        .enable_http1()
+        .build();
+    Client::builder(TokioExecutor::new()).build(connector)
+}
+
+// the certificate is issued for the loopback addresses, not for `localhost`
+fn url(server: &ServerHandle, path: &str) -> String {
+    format!("https://127.0.0.1:{}{path}", server.address().port())
+}
+
+fn get(server: &ServerHandle, path: &str) -> Request<Empty<Bytes>> {
+    Request::get(url(server, path)).body(Empty::new()).unwrap()
+}
+
+#[tokio::test]
+async fn reports_bound_address() {
+    let (_root, mut server) = start().await;
+    assert_ne!(server.address().port(), 0);
+    assert_eq!(
+        server.url(),
+        format!("https://localhost:{}/", server.address().port())
+    );
+    assert_eq!(server.fingerprint().len(), 32 * 3 - 1);
+    server.shutdown(Duration::from_secs(1)).await;
+}
+
+#[tokio::test]
+async fn serves_static_content_over_tls() {
+    let (_root, mut server) = start().await;
+    let client = client(&server);
+    let response = client.request(get(&server, "/")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+    let etag = response.headers()[ETAG].clone();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body.as_ref(), b"<h1>home</h1>");
+    let mut request = get(&server, "/");
+    request.headers_mut().insert(IF_NONE_MATCH, etag);
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    let response = client.request(get(&server, "/style.css")).await.unwrap();
+    assert_eq!(response.headers()["content-encoding"], "br");
+    let response = client.request(get(&server, "/missing")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    server.shutdown(Duration::from_secs(1)).await;
+    assert!(
+        server
+            .summary()
+            .starts_with("4 requests (0 forwarded, 1 errors)")
+    );
+}
+
+#[tokio::test]
+async fn redirects_plain_http_to_https() {
+    let (_root, mut server) = start().await;
+    let address = server.address();
+    let response = tokio::task::spawn_blocking(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"GET /page?q=1 HTTP/1.1\r\nhost: localhost:8443\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    })
+    .await
+    .unwrap();
+    assert!(response.starts_with("HTTP/1.1 307"), "{response}");
+    assert!(
+        response
+            .to_ascii_lowercase()
+            .contains(&format!("{LOCATION}: https://localhost:8443/page?q=1"))
+    );
+    server.shutdown(Duration::from_secs(1)).await;
+}
+
+#[tokio::test]
+async fn stops_accepting_after_shutdown() {
+    let (_root, mut server) = start().await;
+    let client = client(&server);
+    let response = client.request(get(&server, "/")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // the pooled keep-alive connection is closed once idle
+    server.shutdown(Duration::from_secs(5)).await;
+    assert!(TcpStream::connect(server.address()).is_err());
+}
+
+#[tokio::test]
+async fn does_not_wait_for_idle_connections_on_shutdown() {
+    let (_root, mut server) = start().await;
+    // a preconnect that never sends anything, and one stuck in the handshake
+    let _idle = tokio::net::TcpStream::connect(server.address())
+        .await
+        .unwrap();
+    let mut handshake = tokio::net::TcpStream::connect(server.address())
+        .await
+        .unwrap();
+    handshake.write_all(b"\x16\x03\x01").await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    let started = Instant::now();
+    server.shutdown(Duration::from_secs(10)).await;
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn closes_unfinished_connections_after_the_timeout() {
+    let (_root, mut server) = start().await;
+    let mut stream = tokio::net::TcpStream::connect(server.address())
+        .await
+        .unwrap();
+    // the headers never end
+    stream.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    server.shutdown(Duration::from_millis(100)).await;
+    let mut buf = [0u8; 1];
+    let read = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf)).await;
+    assert!(matches!(read, Ok(Ok(0) | Err(_))), "{read:?}");
+}
+
+#[tokio::test]
+async fn records_decoded_bodies_in_har() {
+    let css = b"body { color: red } ".repeat(20);
+    let mut compressed = CompressorWriter::new(Vec::new(), 4096, 11, 22);
+    compressed.write_all(&css).unwrap();
+    let compressed = compressed.into_inner();
+    let root = common::site(&[("style.css", &css), ("style.css.br", &compressed)]);
+    let har: &'static Recorder = Box::leak(Box::new(Recorder::new(
+        root.path().join("session.har"),
+        true,
+    )));
+    let mut server = Server::builder()
+        .address(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .root(root.path())
+        .har(har)
+        .start()
+        .await
+        .unwrap();
+    let response = client(&server)
+        .request(get(&server, "/style.css"))
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["content-encoding"], "br");
+    response.into_body().collect().await.unwrap();
+    server.shutdown(Duration::from_secs(1)).await;
+    assert_eq!(har.write().unwrap(), 1);
+    let har: serde_json::Value = serde_json::from_slice(&fs::read(har.path()).unwrap()).unwrap();
+    let response = &har["log"]["entries"][0]["response"];
+    assert_eq!(response["bodySize"], compressed.len());
+    assert_eq!(response["content"]["size"], css.len());
+    assert_eq!(
+        response["content"]["compression"],
+        css.len() - compressed.len()
+    );
+    assert_eq!(response["content"]["text"], String::from_utf8(css).unwrap());
+    assert!(response["content"].get("encoding").is_none());
+}
+
+#[tokio::test]
+async fn logs_the_bytes_sent_once_the_body_ends() {
+    let (root, mut server) = start().await;
+    let client = client(&server);
+    for path in ["/", "/style.css", "/missing"] {
+        let response = client.request(get(&server, path)).await.unwrap();
+        response.into_body().collect().await.unwrap();
+    }
+    server.shutdown(Duration::from_secs(1)).await;
+    let log = fs::read_to_string(root.path().join(".access.log")).unwrap();
+    let bytes: Vec<(String, u64)> = log
+        .lines()
+        .map(|line| {
+            let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+            (
+                entry["path"].as_str().unwrap().to_string(),
+                entry["bytes"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        bytes,
+        [
+            ("/".to_string(), 13),
+            ("/style.css".to_string(), 10),
+            ("/missing".to_string(), 0)
+        ]
+    );
+}
@@ -0,0 +1,177 @@
+use serve::handler::HeaderSelector;
+use serve::types::DefaultHeaderSelector;
+
+// the content type without its charset parameter
+fn essence(value: &[u8]) -> String {
+    let value = String::from_utf8(value.to_vec()).unwrap();
+    value.split(';').next().unwrap().to_string()
+}
+
+fn header(filename: &str, key: &str) -> Option<String> {
+    let extension = filename.rsplit_once('.').map(|it| it.1).unwrap_or("");
+    let headers = DefaultHeaderSelector.headers_for_extension(filename, extension)?;
+    headers
+        .headers
+        .iter()
+        .find(|line| line.key == key.as_bytes())
+        .map(|line| String::from_utf8(line.value.as_ref().to_vec()).unwrap())
+}
+
+fn compressible(filename: &str) -> bool {
+    let extension = filename.rsplit_once('.').map(|it| it.1).unwrap_or("");
+    DefaultHeaderSelector
+        .headers_for_extension(filename, extension)
+        .unwrap()
+        .compressible
+}
+
+#[test]
+fn maps_common_web_formats() {
+    for (filename, content_type, compressed) in [
+        ("index.html", "text/html", true),
+        ("page.xhtml", "application/xhtml+xml", true),
+        ("app.js.map", "application/json", true),
+        ("site.webmanifest", "application/manifest+json", true),
+        ("data.ld.json", "application/ld+json", true),
+        ("data.schema.json", "application/schema+json", true),
+        ("feed.atom", "application/atom+xml", true),
+        ("feed.rss", "application/rss+xml", true),
+        ("doc.dtd", "application/xml-dtd", true),
+        ("config.yml", "application/yaml", true),
+        ("subtitles.vtt", "text/vtt", true),
+        ("font.woff", "font/woff", false),
+        ("font.ttf", "font/ttf", true),
+        ("photo.jfif", "image/jpeg", false),
+        ("scan.tiff", "image/tiff", true),
+        ("song.mp3", "audio/mpeg", false),
+        ("song.opus", "audio/ogg", false),
+        ("sound.wav", "audio/wav", true),
+        ("clip.webm", "video/webm", false),
+        ("clip.mov", "video/quicktime", false),
+        ("stream.m3u8", "application/vnd.apple.mpegurl", true),
+        ("segment.ts", "video/mp2t", false),
+        ("scene.gltf", "model/gltf+json", true),
+        ("paper.pdf", "application/pdf", false),
+    ] {
+        assert_eq!(
+            header(filename, "content-type").map(|it| essence(it.as_bytes())),
+            Some(content_type.to_string()),
+            "{filename}"
+        );
+        assert_eq!(compressible(filename), compressed, "{filename}");
+    }
+}
+
+#[test]
+fn caches_by_kind() {
+    let cache = |filename| header(filename, "cache-control").unwrap();
+    assert!(cache("photo.jpg").contains("immutable"));
+    assert!(cache("site.webmanifest").contains("max-age=72000"));
+    assert!(cache("stream.m3u8").contains("no-cache"));
+    assert!(cache("sw.js").contains("no-cache"));
+    assert!(cache("clip.mp4").contains("must-revalidate"));
+}
+
+#[test]
+fn leaves_unknown_extensions_unmapped() {
+    assert!(header("notes.unknownext", "content-type").is_none());
+    assert!(header("README", "content-type").is_none());
+}
+
+#[test]
+fn sniffs_common_formats() {
+    let sniffed = |content: &[u8]| {
+        let headers = DefaultHeaderSelector.headers_for_content(content)?;
+        headers
+            .headers
+            .iter()
+            .find(|line| line.key == b"content-type")
+            .map(|line| essence(line.value.as_ref()))
+    };
+    for (content, content_type) in [
+        (b"\x89PNG\r\n\x1a\n".as_slice(), "image/png"),
+        (b"\xff\xd8\xff\xe0", "image/jpeg"),
+        (b"GIF89a", "image/gif"),
+        (b"RIFF\0\0\0\0WEBPVP8 ", "image/webp"),
+        (b"\0\0\0\x1cftypavif", "image/avif"),
+        (b"\0asm\x01\0\0\0", "application/wasm"),
+        (b"%PDF-1.7", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\xef\xbb\xbf { \"key\": 1 }", "application/json"),
+        (b"[1, 2]", "application/json"),
+        (b"\n<!DOCTYPE html><html>", "text/html"),
+        (b"<HTML><body>", "text/html"),
+    ] {
+        assert_eq!(
+            sniffed(content).as_deref(),
+            Some(content_type),
+            "{content:?}"
+        );
+    }
+    for content in [
+        b"plain text".as_slice(),
+        b"{not json",
+        b"[link](url)",
+        b"<svg></svg>",
+        b"\0\x01\x02",
+        b"",
+    ] {
+        assert_eq!(sniffed(content), None, "{content:?}");
+    }
+}
+
+#[test]
+fn types_well_known_files_by_name() {
+    let content_type = |path: &str| {
+        let headers = DefaultHeaderSelector.headers_for_path(path)?;
+        headers
+            .headers
+            .iter()
+            .find(|line| line.key == b"content-type")
+            .map(|line| essence(line.value.as_ref()))
+    };
+    for (path, expected) in [
+        (".well-known/apple-app-site-association", "application/json"),
+        (".well-known/assetlinks.json", "application/json"),
+        (".well-known/security.txt", "text/plain"),
+        (".well-known/webfinger", "application/jrd+json"),
+        (".well-known/host-meta", "application/xrd+xml"),
+        (".well-known/acme-challenge/abc-123", "text/plain"),
+    ] {
+        assert_eq!(content_type(path).as_deref(), Some(expected), "{path}");
+    }
+    // only under .well-known
+    assert_eq!(content_type("apple-app-site-association"), None);
+    assert_eq!(content_type("acme-challenge/abc-123"), None);
+}
+
+#[test]
+fn sends_utf8_for_text_types_only() {
+    for filename in [
+        "index.html",
+        "style.css",
+        "notes.txt",
+        "data.csv",
+        "readme.md",
+        "app.js",
+        "data.json",
+        "feed.atom",
+        "site.webmanifest",
+        "image.svg",
+    ] {
+        assert!(
+            header(filename, "content-type")
+                .unwrap()
+                .ends_with("; charset=utf-8"),
+            "{filename}"
+        );
+    }
+    for filename in ["image.png", "app.wasm", "font.woff2", "paper.pdf"] {
+        assert!(
+            !header(filename, "content-type")
+                .unwrap()
+                .contains("charset"),
+            "{filename}"
+        );
+    }
+}